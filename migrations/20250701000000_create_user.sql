@@ -0,0 +1,8 @@
+-- 用户表, 对应 model::user::User
+-- 与引入迁移之前部署的表结构一致, 之后的变更均以单独的迁移追加, 已有数据库上也会执行
+CREATE TABLE IF NOT EXISTS user (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(64) NOT NULL,
+    password_hash VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL
+);
@@ -0,0 +1,9 @@
+-- 刷新令牌, 只保存令牌的 SHA-256 摘要
+CREATE TABLE IF NOT EXISTS refresh_token (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    user_id INT NOT NULL,
+    token_hash CHAR(64) NOT NULL,
+    expires_at DATETIME NOT NULL,
+    UNIQUE INDEX uq_refresh_token_hash (token_hash),
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+-- 已注销的访问令牌, 过期后可清理
+CREATE TABLE IF NOT EXISTS revoked_token (
+    jti CHAR(36) NOT NULL PRIMARY KEY,
+    expires_at DATETIME NOT NULL,
+    INDEX idx_revoked_token_expires_at (expires_at)
+);
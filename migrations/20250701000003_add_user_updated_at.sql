@@ -0,0 +1,4 @@
+-- created_at 由数据库在插入时填写, updated_at 在每次更新时自动刷新
+ALTER TABLE user
+    MODIFY COLUMN created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP;
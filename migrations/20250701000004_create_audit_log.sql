@@ -0,0 +1,10 @@
+-- 审计日志, 与被审计的操作在同一事务中写入
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    -- 执行操作的用户, 未登录时(如注册)为 NULL
+    actor_id INT NULL,
+    action VARCHAR(64) NOT NULL,
+    target VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_audit_log_created_at (created_at)
+);
@@ -0,0 +1,4 @@
+-- 邮箱可用于登录, 已有用户没有邮箱, 因此允许为 NULL; 唯一索引不限制多个 NULL
+ALTER TABLE user
+    ADD COLUMN email VARCHAR(254) NULL AFTER name,
+    ADD UNIQUE INDEX uq_user_email (email);
@@ -0,0 +1,3 @@
+-- 软删除: 删除用户时只记录时间, 保留行以便恢复与审计
+ALTER TABLE user
+    ADD COLUMN deleted_at DATETIME NULL;
@@ -0,0 +1,10 @@
+-- POST /users 的幂等键, 重复请求直接返回首次的结果; 过期的键在下次查询时清理
+CREATE TABLE IF NOT EXISTS idempotency_key (
+    idem_key VARCHAR(255) NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    -- 用于识别同一个键被用于不同的请求
+    name VARCHAR(64) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_idempotency_key_created_at (created_at),
+    FOREIGN KEY (user_id) REFERENCES user (id) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- 最近一次成功登录的时间, 从未登录时为 NULL
+ALTER TABLE user
+    ADD COLUMN last_login_at DATETIME NULL;
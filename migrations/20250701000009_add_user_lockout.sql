@@ -0,0 +1,4 @@
+-- 连续登录失败的次数, 达到阈值后账户锁定到 locked_until
+ALTER TABLE user
+    ADD COLUMN failed_login_count INT NOT NULL DEFAULT 0,
+    ADD COLUMN locked_until DATETIME NULL;
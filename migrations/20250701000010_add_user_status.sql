@@ -0,0 +1,3 @@
+-- 用户的生命周期状态: active 可以登录, suspended 被停用, pending 尚未启用
+ALTER TABLE user
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'active';
@@ -0,0 +1,4 @@
+-- 用户名唯一, 并发注册同名用户时由数据库拒绝后到的请求
+-- 已有数据库中若存在重名用户, 需先手动处理, 否则迁移失败
+ALTER TABLE user
+    ADD UNIQUE INDEX uq_user_name (name);
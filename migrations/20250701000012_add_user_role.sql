@@ -0,0 +1,3 @@
+-- 用户角色, 对应 server::auth::role; 已有用户均为普通用户
+ALTER TABLE user
+    ADD COLUMN role VARCHAR(32) NOT NULL DEFAULT 'user' AFTER password_hash;
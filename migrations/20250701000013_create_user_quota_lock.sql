@@ -0,0 +1,7 @@
+-- 只有一行的锁表: 检查用户数配额的写事务先锁住这一行再统计, 从而依次执行, 
+-- 不必锁住整张 user 表
+CREATE TABLE IF NOT EXISTS user_quota_lock (
+    id TINYINT NOT NULL PRIMARY KEY
+);
+
+INSERT IGNORE INTO user_quota_lock (id) VALUES (1);
@@ -0,0 +1,4 @@
+-- 修改口令时更新为当时的 UTC 时间, 签发时间早于它的访问令牌一律失效;
+-- 为 NULL 表示口令从未修改过
+ALTER TABLE user
+    ADD COLUMN tokens_valid_after DATETIME NULL DEFAULT NULL AFTER password_hash;
@@ -0,0 +1,81 @@
+/*
+*   cli
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    database::Pool, 
+    model::user::{UserPassword, Username}, 
+    server::auth::{role, Role}
+};
+
+/// 不带子命令时启动 HTTP 服务
+#[derive(Parser)]
+#[command(name = "apb", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// 不派生 Debug, 以免口令出现在日志中
+#[derive(Subcommand)]
+pub enum Command {
+    /// 直接在数据库中创建管理员, 用于首次部署时还没有任何用户的情况
+    CreateAdmin {
+        #[arg(long)]
+        name: String,
+        /// 也可通过环境变量传入, 以免口令出现在进程列表中
+        #[arg(long, env = "APB_ADMIN_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+}
+
+pub async fn run(command: Command, pool: &Pool) -> anyhow::Result<()> {
+    match command {
+        Command::CreateAdmin { name, password } => create_admin(pool, name, password).await,
+    }
+}
+
+async fn create_admin(pool: &Pool, name: String, password: String) -> anyhow::Result<()> {
+    let name = Username::try_from(name).map_err(|err| anyhow::anyhow!("invalid name: {err}"))?;
+    let password = UserPassword::new(password);
+    password.check_strength().map_err(|err| anyhow::anyhow!("weak password: {err}"))?;
+    let password_hash = password.hash_with_random_salt()?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO user (name, password_hash, role) VALUES (?,?,?)")
+        .bind(name.as_str())
+        .bind(password_hash)
+        .bind(role::Admin::NAME)
+        .execute(&mut *tx)
+        .await
+        .map_err(|err| match err.as_database_error() {
+            Some(db_err) if db_err.is_unique_violation() => {
+                anyhow::anyhow!("user `{name}` already exists")
+            }
+            _ => err.into(),
+        })?;
+    sqlx::query("INSERT INTO audit_log (actor_id, action, target) VALUES (NULL, 'user.create_admin', ?)")
+        .bind(name.as_str())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    tracing::info!(%name, "admin user created");
+    Ok(())
+}
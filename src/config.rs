@@ -0,0 +1,684 @@
+/*
+*   config
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{net::SocketAddr, path::Path, str::FromStr, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{
+    database::{deserialize_port, parse_port, prelude::DataBaseConfigOwned}, 
+    model::user::{
+        PasswordHashing, 
+        UserConfig, 
+        UserPasswordProperties, 
+        ARGON2_DEFAULT_ITERATIONS, 
+        BCRYPT_MAX_COST, 
+        BCRYPT_MIN_COST
+    }, 
+    server::{
+        auth::AuthConfig, 
+        client_ip::{Cidr, ClientIpConfig}, 
+        rate_limit::RateLimitConfig, 
+        security_headers::DEFAULT_CSP
+    }, 
+    util::{keys::{load_secret, Keys}, password::{HashAlgorithm, PasswordWithSalt, Peppers, DEFAULT_PEPPER_VERSION}}
+};
+
+/// 未指定 `APB_CONFIG` 时尝试读取的配置文件
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// 程序配置, 从 TOML 文件读取, 同名的环境变量优先于文件中的值
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DataBaseSection,
+    pub jwt: JwtSection,
+    pub server: ServerSection,
+    pub lockout: LockoutSection,
+    pub users: UsersSection,
+    pub password: PasswordSection,
+    pub rate_limit: RateLimitSection,
+}
+
+/// `[database]`, 除 `socket` 外均为必填; 设置了 `socket` 时 `host` 与 `port` 可以省略
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DataBaseSection {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub host: Option<String>,
+    #[serde(deserialize_with = "deserialize_optional_port")]
+    pub port: Option<u16>,
+    pub name: Option<String>,
+    /// Unix 套接字路径, 设置后不再需要 `host` 与 `port`
+    pub socket: Option<String>,
+}
+
+/// `[jwt]`, 未设置的字段使用 [`AuthConfig`] 的默认值
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtSection {
+    /// 签名算法, 默认为 HS256
+    pub algorithm: JwtAlgorithm,
+    /// HS256 的签名密钥, 优先于 `secret_file`
+    pub secret: Option<String>,
+    pub secret_file: Option<String>,
+    /// 轮换前的 HS256 密钥, 只用于验证尚未过期的旧令牌
+    pub previous_secrets: Vec<String>,
+    /// RS256 签名使用的 PEM 格式 RSA 私钥
+    pub private_key_file: Option<String>,
+    /// RS256 验证使用的 PEM 格式 RSA 公钥
+    pub public_key_file: Option<String>,
+    pub token_ttl_secs: Option<i64>,
+    pub refresh_token_ttl_secs: Option<i64>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub leeway_secs: Option<u64>,
+    /// 同时通过 httpOnly cookie 下发访问令牌, 以 cookie 认证时需携带 CSRF 令牌
+    pub cookie_auth: Option<bool>,
+}
+
+/// 令牌的签名算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum JwtAlgorithm {
+    /// 对称密钥, 签发与验证使用同一个 `secret`
+    #[default]
+    HS256,
+    /// RSA 私钥签名、公钥验证, 验证令牌的服务不需要持有私钥
+    RS256,
+}
+
+impl FromStr for JwtAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HS256" => Ok(Self::HS256),
+            "RS256" => Ok(Self::RS256),
+            _ => Err("expected HS256 or RS256".to_string()),
+        }
+    }
+}
+
+/// `[lockout]`, 未设置的字段使用 [`AuthConfig`] 的默认值
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutSection {
+    /// 连续登录失败多少次后锁定账户
+    pub threshold: Option<i32>,
+    /// 锁定时长(秒)
+    pub cooldown_secs: Option<i64>,
+}
+
+/// `[users]`, 未设置的字段使用 [`UserConfig`] 的默认值
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsersSection {
+    /// 未删除用户数的上限, 未设置时不限制
+    pub max_users: Option<u32>,
+    /// `POST /users` 幂等键的有效期(秒)
+    pub idempotency_ttl_secs: Option<i64>,
+}
+
+/// `[password]`
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordSection {
+    /// 新口令使用的散列算法, 默认为 bcrypt; 已有的散列按其前缀识别算法, 切换后仍可校验
+    pub algorithm: HashAlgorithm,
+    /// bcrypt 的代价, 范围为 4..=31, 未设置时为 12
+    pub bcrypt_cost: Option<u32>,
+    /// Argon2id 的迭代次数, 未设置时为 2
+    pub argon2_iterations: Option<u32>,
+    /// 散列前混入口令的服务端密钥, 优先于 `pepper_file`; 均未设置时不使用 pepper
+    pub pepper: Option<String>,
+    pub pepper_file: Option<String>,
+    /// 当前 pepper 的版本, 未设置时为 1
+    pub pepper_version: Option<u32>,
+    /// 轮换前的 pepper, 形如 `"版本:pepper"`, 只用于校验
+    pub previous_peppers: Vec<String>,
+    /// 引入版本前使用的不带版本的 pepper, 只用于校验没有版本前缀的散列
+    pub legacy_pepper: Option<String>,
+}
+
+/// `[rate_limit]`, 登录接口按客户端 IP 的限流, 未设置的字段使用 [`RateLimitConfig`] 的默认值
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSection {
+    /// 每个窗口内允许的请求数
+    pub requests: Option<u32>,
+    /// 窗口长度(秒)
+    pub window_secs: Option<u64>,
+}
+
+/// `[server]`
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// 监听地址, 形如 `0.0.0.0:3000`
+    pub bind: SocketAddr,
+    /// `/users` 与 `/auth` 请求体的最大字节数, 超出时返回 `413 Payload Too Large`
+    pub max_body_bytes: usize,
+    /// PEM 格式的证书链, 服务器证书在前、中间证书在后; 与 `tls_key` 同时设置时启用 HTTPS
+    pub tls_cert: Option<String>,
+    /// PEM 格式的私钥, 支持 PKCS#8、PKCS#1(RSA) 与 SEC1(EC)
+    pub tls_key: Option<String>,
+    /// 附加到所有响应的 `Content-Security-Policy`
+    pub csp: String,
+    /// 是否以 `X-Forwarded-For` 确定客户端地址, 只有位于反向代理之后时才能开启
+    pub trust_forwarded_for: bool,
+    /// 不受登录限流与失败锁定约束的网段, 如 `["10.0.0.0/8"]`
+    pub trusted_networks: Vec<String>,
+    /// 允许跨域访问的来源, 如 `["https://app.example.com"]`; 为空时不允许任何跨域访问
+    pub cors_allowed_origins: Vec<String>,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self { 
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            max_body_bytes: 16 * 1024,
+            tls_cert: None,
+            tls_key: None,
+            csp: DEFAULT_CSP.to_string(),
+            trust_forwarded_for: false,
+            trusted_networks: Vec::new(),
+            cors_allowed_origins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 读取 `APB_CONFIG` 指向的文件, 未设置时读取当前目录下的 `config.toml`(不存在则全部使用默认值),
+    /// 之后以环境变量覆盖
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = match std::env::var("APB_CONFIG") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("failed to read config file `{}`: {err}", path.display()))?;
+        toml::from_str(&content)
+            .map_err(|err| anyhow::anyhow!("invalid config file `{}`: {err}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        let database = &mut self.database;
+        override_with(&mut database.user, "APB_DB_USER")?;
+        override_with(&mut database.password, "APB_DB_PASSWORD")?;
+        override_with(&mut database.host, "APB_DB_HOST")?;
+        if let Ok(port) = std::env::var("APB_DB_PORT") {
+            database.port = Some(parse_port(&port).map_err(|err| anyhow::anyhow!("invalid APB_DB_PORT: {err}"))?);
+        }
+        override_with(&mut database.name, "APB_DB_NAME")?;
+        override_with(&mut database.socket, "APB_DB_SOCKET")?;
+
+        let jwt = &mut self.jwt;
+        if let Ok(algorithm) = std::env::var("APB_JWT_ALGORITHM") {
+            jwt.algorithm = algorithm.parse()
+                .map_err(|err| anyhow::anyhow!("invalid APB_JWT_ALGORITHM `{algorithm}`: {err}"))?;
+        }
+        override_with(&mut jwt.secret, "APB_JWT_SECRET")?;
+        override_with(&mut jwt.secret_file, "APB_JWT_SECRET_FILE")?;
+        if let Ok(secrets) = std::env::var("APB_JWT_PREVIOUS_SECRETS") {
+            // 以逗号分隔
+            jwt.previous_secrets = split_list(&secrets);
+        }
+        override_with(&mut jwt.private_key_file, "APB_JWT_PRIVATE_KEY_FILE")?;
+        override_with(&mut jwt.public_key_file, "APB_JWT_PUBLIC_KEY_FILE")?;
+        override_with(&mut jwt.token_ttl_secs, "APB_TOKEN_TTL_SECS")?;
+        override_with(&mut jwt.refresh_token_ttl_secs, "APB_REFRESH_TOKEN_TTL_SECS")?;
+        override_with(&mut jwt.issuer, "APB_JWT_ISSUER")?;
+        override_with(&mut jwt.audience, "APB_JWT_AUDIENCE")?;
+        override_with(&mut jwt.leeway_secs, "APB_JWT_LEEWAY_SECS")?;
+        override_with(&mut jwt.cookie_auth, "APB_JWT_COOKIE_AUTH")?;
+
+        override_with(&mut self.lockout.threshold, "APB_LOCKOUT_THRESHOLD")?;
+        override_with(&mut self.lockout.cooldown_secs, "APB_LOCKOUT_COOLDOWN_SECS")?;
+
+        override_with(&mut self.users.max_users, "APB_MAX_USERS")?;
+        override_with(&mut self.users.idempotency_ttl_secs, "APB_IDEMPOTENCY_TTL_SECS")?;
+        if let Ok(algorithm) = std::env::var("APB_PASSWORD_ALGORITHM") {
+            self.password.algorithm = algorithm.parse()
+                .map_err(|err| anyhow::anyhow!("invalid APB_PASSWORD_ALGORITHM `{algorithm}`: {err}"))?;
+        }
+        override_with(&mut self.password.bcrypt_cost, "APB_BCRYPT_COST")?;
+        override_with(&mut self.password.argon2_iterations, "APB_ARGON2_ITERATIONS")?;
+        override_with(&mut self.password.pepper, "APB_PASSWORD_PEPPER")?;
+        override_with(&mut self.password.pepper_file, "APB_PASSWORD_PEPPER_FILE")?;
+        override_with(&mut self.password.pepper_version, "APB_PASSWORD_PEPPER_VERSION")?;
+        if let Ok(peppers) = std::env::var("APB_PASSWORD_PREVIOUS_PEPPERS") {
+            self.password.previous_peppers = split_list(&peppers);
+        }
+        override_with(&mut self.password.legacy_pepper, "APB_PASSWORD_LEGACY_PEPPER")?;
+        override_with(&mut self.rate_limit.requests, "APB_RATE_LIMIT_REQUESTS")?;
+        override_with(&mut self.rate_limit.window_secs, "APB_RATE_LIMIT_WINDOW_SECS")?;
+
+        if let Ok(bind) = std::env::var("APB_BIND_ADDR") {
+            self.server.bind = bind.parse()
+                .map_err(|err| anyhow::anyhow!("invalid APB_BIND_ADDR `{bind}`, expected `host:port`: {err}"))?;
+        }
+        if let Ok(max) = std::env::var("APB_MAX_BODY_BYTES") {
+            self.server.max_body_bytes = max.parse()
+                .map_err(|err| anyhow::anyhow!("invalid APB_MAX_BODY_BYTES `{max}`: {err}"))?;
+        }
+        override_with(&mut self.server.tls_cert, "APB_TLS_CERT")?;
+        override_with(&mut self.server.tls_key, "APB_TLS_KEY")?;
+        if let Ok(csp) = std::env::var("APB_CSP") {
+            self.server.csp = csp;
+        }
+        if let Ok(trust) = std::env::var("APB_TRUST_FORWARDED_FOR") {
+            self.server.trust_forwarded_for = trust.parse()
+                .map_err(|err| anyhow::anyhow!("invalid APB_TRUST_FORWARDED_FOR `{trust}`: {err}"))?;
+        }
+        if let Ok(networks) = std::env::var("APB_TRUSTED_NETWORKS") {
+            self.server.trusted_networks = split_list(&networks);
+        }
+        if let Ok(origins) = std::env::var("APB_CORS_ALLOWED_ORIGINS") {
+            self.server.cors_allowed_origins = split_list(&origins);
+        }
+        Ok(())
+    }
+
+    /// 数据库连接配置, 缺失的字段会在错误信息中一并列出
+    pub fn database(&self) -> anyhow::Result<DataBaseConfigOwned> {
+        let DataBaseSection { user, password, host, port, name, socket } = &self.database;
+        let mut missing = Vec::new();
+        if user.is_none() { missing.push("user (APB_DB_USER)"); }
+        if password.is_none() { missing.push("password (APB_DB_PASSWORD)"); }
+        // 通过套接字连接时不使用 host 与 port
+        if socket.is_none() {
+            if host.is_none() { missing.push("host (APB_DB_HOST)"); }
+            if port.is_none() { missing.push("port (APB_DB_PORT)"); }
+        }
+        if name.is_none() { missing.push("name (APB_DB_NAME)"); }
+        match (user, password, name) {
+            (Some(user), Some(password), Some(name)) if missing.is_empty() => Ok(DataBaseConfigOwned {
+                user: user.clone(),
+                password: password.clone(),
+                host: host.clone().unwrap_or_else(|| "localhost".to_string()),
+                port: port.unwrap_or(3306),
+                database: name.clone(),
+                socket: socket.clone(),
+            }),
+            _ => anyhow::bail!("missing database config: {}", missing.join(", ")),
+        }
+    }
+
+    /// 证书与私钥的路径, 均未设置时使用 HTTP, 只设置其一视为配置错误
+    pub fn tls(&self) -> anyhow::Result<Option<(&str, &str)>> {
+        match (&self.server.tls_cert, &self.server.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert.as_str(), key.as_str()))),
+            (None, None) => Ok(None),
+            _ => anyhow::bail!("server.tls_cert (APB_TLS_CERT) and server.tls_key (APB_TLS_KEY) must be set together"),
+        }
+    }
+
+    /// 确定客户端地址的方式与可信网段, 网段格式有误时报错
+    pub fn client_ip(&self) -> anyhow::Result<ClientIpConfig> {
+        let trusted_networks = self.server.trusted_networks
+            .iter()
+            .map(|network| network.parse::<Cidr>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| anyhow::anyhow!("server.trusted_networks (APB_TRUSTED_NETWORKS): {err}"))?;
+        Ok(ClientIpConfig { trust_forwarded_for: self.server.trust_forwarded_for, trusted_networks })
+    }
+
+    /// 令牌的签名密钥, 所选算法缺少密钥或配置了另一种算法的密钥时报错
+    pub fn keys(&self) -> anyhow::Result<Keys> {
+        let jwt = &self.jwt;
+        match jwt.algorithm {
+            JwtAlgorithm::HS256 => {
+                if jwt.private_key_file.is_some() || jwt.public_key_file.is_some() {
+                    anyhow::bail!("jwt.private_key_file and jwt.public_key_file require jwt.algorithm = \"RS256\"");
+                }
+                let secret = load_secret(jwt.secret.as_deref(), jwt.secret_file.as_deref())?;
+                Ok(Keys::new(&secret).with_previous_secrets(&jwt.previous_secrets))
+            }
+            JwtAlgorithm::RS256 => {
+                if jwt.secret.is_some() || jwt.secret_file.is_some() || !jwt.previous_secrets.is_empty() {
+                    anyhow::bail!("jwt.secret, jwt.secret_file and jwt.previous_secrets are only used with HS256");
+                }
+                let (Some(private_key_file), Some(public_key_file)) = (&jwt.private_key_file, &jwt.public_key_file) else {
+                    anyhow::bail!(
+                        "jwt.algorithm = \"RS256\" requires jwt.private_key_file and jwt.public_key_file \
+                        (APB_JWT_PRIVATE_KEY_FILE, APB_JWT_PUBLIC_KEY_FILE)"
+                    );
+                };
+                let read = |path: &str| std::fs::read(path)
+                    .map_err(|err| anyhow::anyhow!("failed to read JWT key file `{path}`: {err}"));
+                Keys::from_rsa_pem(&read(private_key_file)?, &read(public_key_file)?)
+                    .map_err(|err| anyhow::anyhow!("invalid RSA key in jwt.private_key_file or jwt.public_key_file: {err}"))
+            }
+        }
+    }
+
+    /// 用户管理的配置, 幂等键有效期不为正数时报错
+    pub fn users(&self) -> anyhow::Result<UserConfig> {
+        let default = UserConfig::default();
+        let config = UserConfig {
+            max_users: self.users.max_users.map(i64::from),
+            idempotency_ttl: self.users.idempotency_ttl_secs.unwrap_or(default.idempotency_ttl),
+        };
+        if config.idempotency_ttl <= 0 {
+            anyhow::bail!(
+                "users.idempotency_ttl_secs (APB_IDEMPOTENCY_TTL_SECS) must be positive, got {}", 
+                config.idempotency_ttl
+            );
+        }
+        Ok(config)
+    }
+
+    /// 用户口令的散列算法与代价, 代价超出算法允许的范围时报错
+    pub fn password_hashing(&self) -> anyhow::Result<PasswordHashing> {
+        let password = &self.password;
+        let bcrypt_cost = password.bcrypt_cost.unwrap_or(<UserPasswordProperties as PasswordWithSalt>::COST);
+        if !(BCRYPT_MIN_COST..=BCRYPT_MAX_COST).contains(&bcrypt_cost) {
+            anyhow::bail!(
+                "password.bcrypt_cost (APB_BCRYPT_COST) must be in {BCRYPT_MIN_COST}..={BCRYPT_MAX_COST}, got {bcrypt_cost}"
+            );
+        }
+        let argon2_iterations = password.argon2_iterations.unwrap_or(ARGON2_DEFAULT_ITERATIONS);
+        if argon2_iterations == 0 {
+            anyhow::bail!("password.argon2_iterations (APB_ARGON2_ITERATIONS) must be positive");
+        }
+        let cost = match password.algorithm {
+            HashAlgorithm::Bcrypt => bcrypt_cost,
+            HashAlgorithm::Argon2 => argon2_iterations,
+        };
+        Ok(PasswordHashing { algorithm: password.algorithm, cost })
+    }
+
+    /// 口令的 pepper, 未设置时返回 `None`; 
+    /// 没有当前 pepper 却设置了版本或旧 pepper、pepper 为空、版本重复或旧 pepper 格式有误时报错
+    pub fn peppers(&self) -> anyhow::Result<Option<Peppers>> {
+        let password = &self.password;
+        let pepper = if let Some(pepper) = &password.pepper {
+            pepper.as_bytes().to_vec()
+        } else if let Some(path) = &password.pepper_file {
+            std::fs::read(path)
+                .map_err(|err| anyhow::anyhow!("failed to read password.pepper_file `{path}`: {err}"))?
+                .trim_ascii_end()
+                .to_vec()
+        } else if password.pepper_version.is_some() || !password.previous_peppers.is_empty() || password.legacy_pepper.is_some() {
+            anyhow::bail!(
+                "password.pepper_version, password.previous_peppers and password.legacy_pepper \
+                require password.pepper or password.pepper_file (APB_PASSWORD_PEPPER, APB_PASSWORD_PEPPER_FILE)"
+            );
+        } else {
+            return Ok(None);
+        };
+        let mut peppers = Peppers::new(password.pepper_version.unwrap_or(DEFAULT_PEPPER_VERSION), pepper)?;
+        for entry in &password.previous_peppers {
+            let (version, pepper) = entry.split_once(':').ok_or_else(|| anyhow::anyhow!(
+                "invalid password.previous_peppers (APB_PASSWORD_PREVIOUS_PEPPERS) entry, expected `version:pepper`"
+            ))?;
+            let version = version.parse().map_err(|err| anyhow::anyhow!(
+                "invalid pepper version `{version}` in password.previous_peppers (APB_PASSWORD_PREVIOUS_PEPPERS): {err}"
+            ))?;
+            peppers = peppers.with_previous(version, pepper.as_bytes().to_vec())?;
+        }
+        if let Some(legacy) = &password.legacy_pepper {
+            peppers = peppers.with_legacy(legacy.as_bytes().to_vec())?;
+        }
+        Ok(Some(peppers))
+    }
+
+    /// 登录限流的配置, 请求数或窗口长度为 0 时报错
+    pub fn rate_limit(&self) -> anyhow::Result<RateLimitConfig> {
+        let default = RateLimitConfig::default();
+        let requests = self.rate_limit.requests.unwrap_or(default.requests);
+        if requests == 0 {
+            anyhow::bail!("rate_limit.requests (APB_RATE_LIMIT_REQUESTS) must be positive");
+        }
+        let window = match self.rate_limit.window_secs {
+            Some(0) => anyhow::bail!("rate_limit.window_secs (APB_RATE_LIMIT_WINDOW_SECS) must be positive"),
+            Some(secs) => Duration::from_secs(secs),
+            None => default.window,
+        };
+        Ok(RateLimitConfig { requests, window })
+    }
+
+    /// 令牌的签发与校验配置, 有效期不为正数时报错, 以免签发出即刻过期的令牌; 
+    /// 锁定阈值不为正数或锁定时长为负数时同样报错
+    pub fn auth(&self) -> anyhow::Result<AuthConfig> {
+        let jwt = &self.jwt;
+        let default = AuthConfig::default();
+        let config = AuthConfig {
+            token_ttl: jwt.token_ttl_secs.unwrap_or(default.token_ttl),
+            refresh_token_ttl: jwt.refresh_token_ttl_secs.unwrap_or(default.refresh_token_ttl),
+            issuer: jwt.issuer.clone().unwrap_or(default.issuer),
+            audience: jwt.audience.clone().unwrap_or(default.audience),
+            leeway: jwt.leeway_secs.unwrap_or(default.leeway),
+            lockout_threshold: self.lockout.threshold.unwrap_or(default.lockout_threshold),
+            lockout_cooldown: self.lockout.cooldown_secs.unwrap_or(default.lockout_cooldown),
+            cookie_auth: jwt.cookie_auth.unwrap_or(default.cookie_auth),
+        };
+        if config.token_ttl <= 0 {
+            anyhow::bail!("jwt.token_ttl_secs (APB_TOKEN_TTL_SECS) must be positive, got {}", config.token_ttl);
+        }
+        if config.refresh_token_ttl <= 0 {
+            anyhow::bail!(
+                "jwt.refresh_token_ttl_secs (APB_REFRESH_TOKEN_TTL_SECS) must be positive, got {}", 
+                config.refresh_token_ttl
+            );
+        }
+        // 阈值为 0 或负数时第一次登录失败就会锁定账户
+        if config.lockout_threshold <= 0 {
+            anyhow::bail!(
+                "lockout.threshold (APB_LOCKOUT_THRESHOLD) must be positive, got {}", 
+                config.lockout_threshold
+            );
+        }
+        if config.lockout_cooldown < 0 {
+            anyhow::bail!(
+                "lockout.cooldown_secs (APB_LOCKOUT_COOLDOWN_SECS) must not be negative, got {}", 
+                config.lockout_cooldown
+            );
+        }
+        Ok(config)
+    }
+}
+
+// 字段缺省时由 `#[serde(default)]` 处理, 这里只会遇到有值的情况
+fn deserialize_optional_port<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u16>, D::Error> {
+    deserialize_port(deserializer).map(Some)
+}
+
+/// 逗号分隔的列表, 忽略空项
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 环境变量 `key` 存在时以其值覆盖 `slot`
+fn override_with<T>(slot: &mut Option<T>, key: &str) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display {
+    if let Ok(value) = std::env::var(key) {
+        let parsed = value.parse()
+            .map_err(|err| anyhow::anyhow!("invalid {key} `{value}`: {err}"))?;
+        *slot = Some(parsed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_database_fields_are_listed() {
+        let err = Config::default().database().unwrap_err().to_string();
+        assert!(err.contains("user (APB_DB_USER)"), "{err}");
+        assert!(err.contains("host (APB_DB_HOST)"), "{err}");
+        assert!(err.contains("name (APB_DB_NAME)"), "{err}");
+    }
+
+    #[test]
+    fn socket_makes_host_and_port_optional() {
+        let mut config = Config::default();
+        config.database = DataBaseSection {
+            user: Some("apb".to_string()),
+            password: Some("secret".to_string()),
+            name: Some("apb".to_string()),
+            socket: Some("/run/mysqld/mysqld.sock".to_string()),
+            ..Default::default()
+        };
+        assert!(config.database().is_ok());
+    }
+
+    #[test]
+    fn non_positive_idempotency_ttl_is_rejected() {
+        let mut config = Config::default();
+        assert_eq!(config.users().unwrap().idempotency_ttl, 24 * 3600);
+        config.users.idempotency_ttl_secs = Some(0);
+        assert!(config.users().is_err());
+        config.users.idempotency_ttl_secs = Some(-1);
+        assert!(config.users().is_err());
+    }
+
+    #[test]
+    fn bcrypt_cost_must_be_in_range() {
+        let mut config = Config::default();
+        assert_eq!(config.password_hashing().unwrap(), PasswordHashing { algorithm: HashAlgorithm::Bcrypt, cost: 12 });
+        config.password.bcrypt_cost = Some(BCRYPT_MIN_COST - 1);
+        assert!(config.password_hashing().is_err());
+        config.password.bcrypt_cost = Some(BCRYPT_MAX_COST + 1);
+        assert!(config.password_hashing().is_err());
+        config.password.bcrypt_cost = Some(BCRYPT_MIN_COST);
+        assert_eq!(config.password_hashing().unwrap().cost, BCRYPT_MIN_COST);
+    }
+
+    #[test]
+    fn argon2_uses_its_own_cost() {
+        let mut config = Config::default();
+        config.password.algorithm = HashAlgorithm::Argon2;
+        assert_eq!(
+            config.password_hashing().unwrap(), 
+            PasswordHashing { algorithm: HashAlgorithm::Argon2, cost: ARGON2_DEFAULT_ITERATIONS }
+        );
+        config.password.argon2_iterations = Some(0);
+        assert!(config.password_hashing().is_err());
+    }
+
+    #[test]
+    fn pepper_is_optional() {
+        assert!(Config::default().peppers().unwrap().is_none());
+    }
+
+    #[test]
+    fn pepper_settings_require_current_pepper() {
+        let mut config = Config::default();
+        config.password.pepper_version = Some(2);
+        assert!(config.peppers().is_err());
+        config.password = PasswordSection { previous_peppers: vec!["1:old".to_string()], ..Default::default() };
+        assert!(config.peppers().is_err());
+        config.password = PasswordSection { legacy_pepper: Some("legacy".to_string()), ..Default::default() };
+        assert!(config.peppers().is_err());
+    }
+
+    #[test]
+    fn invalid_peppers_are_rejected() {
+        let mut config = Config::default();
+        config.password.pepper = Some(String::new());
+        assert!(config.peppers().is_err());
+
+        config.password = PasswordSection { 
+            pepper: Some("current".to_string()), 
+            pepper_version: Some(2), 
+            previous_peppers: vec!["1:old".to_string()], 
+            ..Default::default() 
+        };
+        assert!(config.peppers().unwrap().is_some());
+        config.password.previous_peppers = vec!["old".to_string()];
+        assert!(config.peppers().is_err());
+        config.password.previous_peppers = vec!["v1:old".to_string()];
+        assert!(config.peppers().is_err());
+        // 与当前版本重复
+        config.password.previous_peppers = vec!["2:old".to_string()];
+        assert!(config.peppers().is_err());
+    }
+
+    #[test]
+    fn zero_rate_limit_is_rejected() {
+        let mut config = Config::default();
+        config.rate_limit.requests = Some(0);
+        assert!(config.rate_limit().is_err());
+        config.rate_limit = RateLimitSection { requests: Some(5), window_secs: Some(0) };
+        assert!(config.rate_limit().is_err());
+        config.rate_limit.window_secs = Some(30);
+        let limit = config.rate_limit().unwrap();
+        assert_eq!((limit.requests, limit.window), (5, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn invalid_lockout_is_rejected() {
+        let mut config = Config::default();
+        assert!(config.auth().is_ok());
+        config.lockout.threshold = Some(0);
+        assert!(config.auth().is_err());
+        config.lockout = LockoutSection { threshold: Some(3), cooldown_secs: Some(-1) };
+        assert!(config.auth().is_err());
+        config.lockout.cooldown_secs = Some(0);
+        assert!(config.auth().is_ok());
+    }
+
+    #[test]
+    fn rs256_requires_both_key_files() {
+        let mut config = Config::default();
+        config.jwt.algorithm = JwtAlgorithm::RS256;
+        config.jwt.private_key_file = Some("/nonexistent/private.pem".to_string());
+        let err = config.keys().err().unwrap().to_string();
+        assert!(err.contains("public_key_file"), "{err}");
+    }
+
+    #[test]
+    fn key_files_are_rejected_with_hs256() {
+        let mut config = Config::default();
+        config.jwt.secret = Some("secret".to_string());
+        assert!(config.keys().is_ok());
+        config.jwt.public_key_file = Some("/nonexistent/public.pem".to_string());
+        assert!(config.keys().is_err());
+    }
+
+    #[test]
+    fn jwt_algorithm_parses_known_names() {
+        assert_eq!("RS256".parse::<JwtAlgorithm>(), Ok(JwtAlgorithm::RS256));
+        assert_eq!("HS256".parse::<JwtAlgorithm>(), Ok(JwtAlgorithm::HS256));
+        assert!("none".parse::<JwtAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn list_values_are_split_on_commas() {
+        assert_eq!(split_list(" https://a.example , ,https://b.example"), ["https://a.example", "https://b.example"]);
+        assert!(split_list("").is_empty());
+    }
+}
@@ -17,53 +17,275 @@
 */
 #![feature(allocator_api)]
 
-use std::sync::LazyLock;
+use std::{net::SocketAddr, sync::Arc};
 
-use axum::Router;
-use sqlx::MySqlPool;
+use axum::{extract::DefaultBodyLimit, middleware, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::{predicate::{And, DefaultPredicate, Predicate, SizeAbove}, CompressionLayer}, 
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer}, 
+    trace::{DefaultOnResponse, TraceLayer}
+};
 
+mod cli;
+mod config;
 mod database;
 use database::prelude::*;
 mod model;
 use model::user::user_router;
 
-use crate::{server::auth::auth_router, util::keys};
+use crate::{
+    server::{
+        auth::auth_router, 
+        cors::cors_layer,
+        health::health_router, 
+        metrics::{install_recorder, metrics_router, track_metrics},
+        openapi::openapi_router,
+        rate_limit::RateLimiter, 
+        security_headers::security_headers_layer,
+        state::AppState,
+        trace
+    }, 
+    util::password
+};
 mod util;
 mod server;
-
-const DATABASE_URL: DataBaseUrl<'_, mark::MariaDB> = DataBaseUrl::<'_, mark::MariaDB>::new(
-    DataBaseConfig {
-        user: "apb", 
-        // todo: 不应把密码写在代码中
-        password: "1145141919810", 
-        host: "localhost",  
-        port: 3306, 
-        database: "apb_database"
-    }
-);
-
-static KEYS: LazyLock<keys::Keys> = LazyLock::new(|| {
-    // todo: 不应把密码写在代码中
-    let secret = "Free as in Freedom";
-    keys::Keys::new(secret.as_bytes())
-});
+#[cfg(test)]
+mod testing;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = cli::Cli::parse();
 
-    // initialize tracing
-    tracing_subscriber::fmt::init();
+    init_tracing();
 
-    let pool = MySqlPool::connect(&DATABASE_URL.get_url()).await?;
+    let config = config::Config::load()?;
+    let database_config = config.database()?;
+    let database_url = DataBaseUrl::<'_, database::DataBase>::try_from(database_config.borrow())?.get_url();
+    let pool = database::Pool::connect(&database_url).await?;
+    // migrations/ 中的脚本按文件名前缀的时间戳依次执行, 已执行过的会被跳过;
+    // 新的迁移只能追加, 不能修改已发布的脚本
+    sqlx::migrate!().run(&pool).await?;
+    model::user::set_password_hashing(config.password_hashing()?)?;
+    if let Some(peppers) = config.peppers()? {
+        password::set_pepper(peppers)?;
+    }
+    if let Some(command) = cli.command {
+        let result = cli::run(command, &pool).await;
+        pool.close().await;
+        return result;
+    }
+    let keys = Arc::new(config.keys()?);
+    let client_ip = Arc::new(config.client_ip()?);
+    let state = AppState { 
+        pool: pool.clone(), 
+        users: Arc::new(pool.clone()), 
+        keys, 
+        auth_config: config.auth()?, 
+        client_ip: client_ip.clone(), 
+        user_config: config.users()?, 
+    };
+    let limiter = Arc::new(RateLimiter::new(config.rate_limit()?, client_ip));
+    let metrics = install_recorder()?;
     
-    let app = Router::new()
-        .nest("/users", user_router())
-        .with_state(pool.clone())
-        .nest("/auth", auth_router())
-        .with_state((pool, &KEYS));
+    let app = build_router(state, limiter, metrics, config.server.max_body_bytes);
+    // 发布构建即使启用了 `dev` 也不会包含调试接口
+    #[cfg(all(feature = "dev", debug_assertions))]
+    let app = app.merge(server::debug::debug_router(&config));
+    let app = app
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(trace::make_request_span)
+                        .on_response(DefaultOnResponse::new().level(tracing::Level::INFO))
+                )
+                .layer(PropagateRequestIdLayer::x_request_id())
+                .layer(security_headers_layer(&config.server.csp)?)
+                .layer(compression_layer())
+        )
+        .layer(cors_layer(&config.server.cors_allowed_origins)?);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, app).await?;
+    // 限流需要获取客户端的连接地址
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    if let Some((cert, key)) = config.tls()? {
+        let tls = RustlsConfig::from_pem_file(cert, key).await
+            .map_err(|err| anyhow::anyhow!("failed to load TLS certificate `{cert}` or key `{key}`: {err}"))?;
+        let handle = axum_server::Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                shutdown_signal().await;
+                handle.graceful_shutdown(None);
+            }
+        });
+        tracing::info!(addr = %config.server.bind, "listening with TLS");
+        // ALPN 同时协商 HTTP/2 与 HTTP/1.1
+        axum_server::bind_rustls(config.server.bind, tls)
+            .handle(handle)
+            .serve(service)
+            .await
+            .map_err(|err| anyhow::anyhow!("failed to serve on {}: {err}", config.server.bind))?;
+    } else {
+        let listener = tokio::net::TcpListener::bind(config.server.bind).await
+            .map_err(|err| anyhow::anyhow!("failed to listen on {}: {err}", config.server.bind))?;
+        tracing::info!(addr = %listener.local_addr()?, "listening");
+        axum::serve(listener, service)
+            .with_graceful_shutdown(shutdown_signal())
+            .await?;
+    }
+
+    pool.close().await;
+    tracing::info!("shutdown complete");
 
     Ok(())
 }
+
+/// 小于该字节数的响应不压缩, 压缩收益抵不过开销;
+/// 图片、gRPC 与 SSE 等已由 `DefaultPredicate` 排除
+const MIN_COMPRESS_BYTES: u16 = 1024;
+
+/// 按 `Accept-Encoding` 以 gzip 或 brotli 压缩不小于 [`MIN_COMPRESS_BYTES`] 的响应
+fn compression_layer() -> CompressionLayer<And<DefaultPredicate, SizeAbove>> {
+    CompressionLayer::new().compress_when(DefaultPredicate::new().and(SizeAbove::new(MIN_COMPRESS_BYTES)))
+}
+
+/// 本程序默认输出 info 级别日志, 依赖库只输出 warn 及以上; 
+/// 请求日志由本程序配置的 `TraceLayer` 产生, 因此同样使用 info
+const DEFAULT_LOG_FILTER: &str = "warn,auto_planning_backend=info,tower_http::trace=info";
+
+// 日志过滤规则依次取自 `APB_LOG`、`RUST_LOG`, 均未设置时使用 `DEFAULT_LOG_FILTER`;
+// `APB_LOG_FORMAT=json` 时输出 JSON 格式, 便于日志系统采集
+fn init_tracing() {
+    use tracing_subscriber::EnvFilter;
+
+    let filter = EnvFilter::try_from_env("APB_LOG")
+        .or_else(|_| EnvFilter::try_from_default_env())
+        .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if std::env::var("APB_LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json")) {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+// 所有子路由共用同一个 `AppState`, 只在合并完成后调用一次 `with_state`
+fn build_router(
+    state: AppState, limiter: Arc<RateLimiter>, metrics: PrometheusHandle, max_body_bytes: usize
+) -> Router {
+    // 这些接口的请求体都很小, 限制大小以免占用过多内存
+    let body_limit = DefaultBodyLimit::max(max_body_bytes);
+    Router::new()
+        .nest("/users", user_router(limiter.clone()).layer(body_limit))
+        .nest("/auth", auth_router(limiter).layer(body_limit))
+        .merge(health_router())
+        .merge(openapi_router())
+        .route_layer(middleware::from_fn(track_metrics))
+        // 抓取 /metrics 本身不计入请求指标
+        .merge(metrics_router(metrics))
+        .with_state(state)
+}
+
+// 收到 SIGINT 或 SIGTERM 时返回, 之后不再接受新连接并等待进行中的请求完成
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutdown signal received, draining in-flight requests");
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::{header, Request, StatusCode}};
+
+    use super::*;
+    use crate::{
+        model::repository::{InMemoryUsers, NewUser, UserRepository}, 
+        server::security_headers::DEFAULT_CSP, 
+        testing::{json_request, lazy_pool, send, test_app, test_state, test_state_with}
+    };
+
+    /// 不访问数据库的路由; 这些请求在访问数据库之前就已被拒绝或处理完毕
+    fn app() -> Router {
+        test_app(test_state(lazy_pool()))
+    }
+
+    #[tokio::test]
+    async fn non_json_content_type_is_unsupported() {
+        let request = Request::post("/auth/refresh")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(r#"{ "refreshToken": "token" }"#))
+            .unwrap();
+        let (status, _, body) = send(app(), request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], 415);
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_rejected() {
+        // `test_app` 限制请求体为 64 KiB
+        let token = "a".repeat(65 * 1024);
+        let request = json_request("POST", "/auth/refresh", serde_json::json!({ "refreshToken": token }));
+        let (status, _, _) = send(app(), request).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn security_headers_are_set() {
+        let app = app().layer(security_headers_layer(DEFAULT_CSP).unwrap());
+        // 错误响应同样带有这些响应头
+        for uri in ["/health", "/no-such-route"] {
+            let (_, headers, _) = send(app.clone(), Request::get(uri).body(Body::empty()).unwrap()).await;
+            assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+            assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+            assert_eq!(headers[header::CONTENT_SECURITY_POLICY], DEFAULT_CSP);
+        }
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_gzipped() {
+        let users = Arc::new(InMemoryUsers::default());
+        for i in 0..20 {
+            let name = format!("user{i:02}");
+            let user = NewUser { name: &name, email: None, password_hash: "hash".to_string(), idempotency_key: None };
+            users.create(user, None).await.unwrap();
+        }
+        let app = test_app(test_state_with(lazy_pool(), users)).layer(compression_layer());
+        let request = |uri: &str| Request::get(uri)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, headers, _) = send(app.clone(), request("/users?limit=20")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_ENCODING], "gzip");
+        // 小于 `MIN_COMPRESS_BYTES` 的响应原样返回
+        let (_, headers, body) = send(app, request("/health")).await;
+        assert!(!headers.contains_key(header::CONTENT_ENCODING));
+        assert_eq!(body["status"], "ok");
+    }
+}
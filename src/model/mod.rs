@@ -1 +1,2 @@
+pub mod repository;
 pub mod user;
\ No newline at end of file
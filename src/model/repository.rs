@@ -0,0 +1,879 @@
+/*
+*   model::repository
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{future::Future, pin::Pin};
+
+use futures_util::stream::BoxStream;
+use sqlx::{types::chrono::{self, NaiveDateTime}, Transaction};
+
+use crate::{
+    database::{Driver, Pool}, 
+    model::user::{fetch_user, fetch_user_by_name, User, UserId, UserStatus}, 
+    util::error::is_connection_error
+};
+
+/// 仓库方法返回的 future; 使用装箱的 future, 使 [`UserRepository`] 可以作为 trait 对象保存在状态中
+pub type RepoFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, sqlx::Error>> + Send + 'a>>;
+
+/// 用户的存储, 处理函数通过 `State<Arc<dyn UserRepository>>` 使用, 不直接依赖 SQL
+/// 
+/// 查询结果均不包含已软删除的用户; 写操作中用户名或邮箱冲突、配额不足等预期内的情况
+/// 以 [`WriteOutcome`] 返回, 只有数据库本身的错误才返回 `Err`
+pub trait UserRepository: Send + Sync {
+    /// 在同一事务中写入用户、审计日志以及幂等键(如果有); 
+    /// 设置了 `max_users` 且用户数已达上限时不写入任何内容
+    fn create<'a>(&'a self, user: NewUser<'a>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome>;
+    /// 在同一事务中写入全部用户及其审计日志, 任一用户冲突或超出配额时都不写入
+    fn create_many<'a>(&'a self, users: Vec<NewUser<'a>>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome>;
+    /// 按用户名创建或更新用户, 已软删除的同名用户会被恢复
+    fn upsert<'a>(&'a self, user: UpsertUser<'a>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome>;
+    fn update<'a>(&'a self, id: UserId, changes: UserChanges<'a>) -> RepoFuture<'a, WriteOutcome>;
+    /// 软删除用户, 同时作废其刷新令牌
+    fn delete(&self, id: UserId) -> RepoFuture<'_, WriteOutcome>;
+    /// 恢复软删除的用户
+    fn restore(&self, id: UserId, max_users: Option<i64>) -> RepoFuture<'_, WriteOutcome>;
+    fn find_by_id(&self, id: UserId) -> RepoFuture<'_, Option<User>>;
+    fn find_by_name<'a>(&'a self, name: &'a str) -> RepoFuture<'a, Option<User>>;
+    fn find_by_email<'a>(&'a self, email: &'a str) -> RepoFuture<'a, Option<User>>;
+    /// 记录一次失败的登录, 连续失败达到 `threshold` 次时锁定到 `locked_until` 并清零计数
+    fn record_failed_login(&self, id: UserId, threshold: i32, locked_until: NaiveDateTime) -> RepoFuture<'_, ()>;
+    /// 清零失败登录计数并解除锁定
+    fn reset_failed_logins(&self, id: UserId) -> RepoFuture<'_, ()>;
+    /// 记录成功登录的时间; 登录不算作对用户资料的修改, 不改变 `updated_at`
+    fn record_login(&self, id: UserId, at: NaiveDateTime) -> RepoFuture<'_, ()>;
+    /// 替换口令散列而不作废已签发的令牌, 只用于以同一口令升级散列算法或参数
+    fn replace_password_hash(&self, id: UserId, password_hash: String) -> RepoFuture<'_, ()>;
+    /// 分页列出用户, 同时返回符合条件的总数
+    fn list<'a>(&'a self, filter: &'a UserFilter) -> RepoFuture<'a, (Vec<User>, i64)>;
+    /// 用户名在 `names` 中或邮箱在 `emails` 中的用户(包括已软删除的), 返回其用户名与邮箱
+    fn find_taken<'a>(&'a self, names: &'a [&'a str], emails: &'a [&'a str]) -> RepoFuture<'a, Vec<(String, Option<String>)>>;
+    /// 未删除的用户数
+    fn count(&self) -> RepoFuture<'_, i64>;
+    /// 按 id 顺序逐行读取全部未删除的用户, 不一次性载入内存
+    fn export(&self) -> BoxStream<'_, Result<UserSummary, sqlx::Error>>;
+    /// 清理 `expired_before` 之前的幂等键, 并返回 `key` 对应的用户名
+    fn find_idempotency_key<'a>(&'a self, key: &'a str, expired_before: NaiveDateTime) -> RepoFuture<'a, Option<String>>;
+}
+
+/// 执行只读查询, 遇到连接错误(如数据库重启后连接池中失效的连接)时再执行一次
+/// 
+/// 只能用于没有副作用的查询; 写操作重试可能重复写入, 应直接返回
+/// [`database_error`](crate::util::error::database_error) 给出的 503
+pub async fn retry_read<T, F, Fut>(mut query: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>> {
+    match query().await {
+        Err(err) if is_connection_error(&err) => {
+            tracing::warn!(%err, "connection error on read, retrying once");
+            query().await
+        }
+        result => result,
+    }
+}
+
+/// 待创建的用户, 口令已经散列
+pub struct NewUser<'a> {
+    pub name: &'a str,
+    pub email: Option<&'a str>,
+    pub password_hash: String,
+    pub idempotency_key: Option<&'a str>,
+}
+
+/// [`UserRepository::upsert`] 写入的用户, 口令已经散列
+pub struct UpsertUser<'a> {
+    pub name: &'a str,
+    pub password_hash: String,
+    /// 未指定时, 新用户为 `user`, 已有用户保持原角色
+    pub role: Option<&'a str>,
+}
+
+/// [`UserRepository::update`] 修改的字段, 为 `None` 的字段保持不变
+pub struct UserChanges<'a> {
+    pub name: Option<&'a str>,
+    pub password_hash: Option<String>,
+}
+
+/// 导出用户时每行的内容
+pub type UserSummary = (UserId, String, NaiveDateTime);
+
+/// 写操作的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Created,
+    /// 更新、删除或恢复了已有的用户
+    Changed,
+    NotFound,
+    NameTaken,
+    EmailTaken,
+    /// 写入后未删除的用户数将超过 `max_users`
+    QuotaExceeded,
+}
+
+impl WriteOutcome {
+    /// 唯一索引冲突对应的结果, 其他错误返回 `None`
+    fn from_unique_violation(err: &sqlx::Error) -> Option<Self> {
+        match err.as_database_error() {
+            // MySQL 1062: Duplicate entry
+            // MySQL 不提供约束名, 只能从错误信息中区分是哪个唯一索引
+            Some(db_err) if db_err.is_unique_violation() && db_err.message().contains("uq_user_email") => {
+                Some(WriteOutcome::EmailTaken)
+            }
+            Some(db_err) if db_err.is_unique_violation() => Some(WriteOutcome::NameTaken),
+            _ => None,
+        }
+    }
+}
+
+/// 将唯一索引冲突转换为对应的 [`WriteOutcome`]
+fn unique_violation_as_outcome(result: Result<WriteOutcome, sqlx::Error>) -> Result<WriteOutcome, sqlx::Error> {
+    result.or_else(|err| WriteOutcome::from_unique_violation(&err).ok_or(err))
+}
+
+/// 锁住 `user_quota_lock` 中唯一的一行, 直到事务结束
+/// 
+/// 检查配额的写事务都先获取这把锁, 因此统计用户数与随后的写入之间不会被其他此类事务穿插; 
+/// 只锁一行, 不检查配额的写入(如修改用户名)不受影响。
+/// 锁应在事务中的第一次普通读之前获取, 使之后的统计读到锁释放前已提交的写入
+async fn lock_quota(tx: &mut Transaction<'_, Driver>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT id FROM user_quota_lock WHERE id=1 FOR UPDATE")
+        .execute(&mut **tx)
+        .await
+        .map(|_| ())
+}
+
+/// 写入 `adding` 个用户后未删除的用户数是否将超过 `max_users`; 调用前需已 [`lock_quota`]
+async fn exceeds_quota(tx: &mut Transaction<'_, Driver>, adding: i64, max_users: Option<i64>) -> Result<bool, sqlx::Error> {
+    let Some(max) = max_users else {
+        return Ok(false);
+    };
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user WHERE deleted_at IS NULL")
+        .fetch_one(&mut **tx)
+        .await?;
+    Ok(count + adding > max)
+}
+
+/// [`UserRepository::list`] 的条件, 未设置的条件不参与过滤
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    /// 按用户名前缀搜索
+    pub name_prefix: Option<String>,
+    pub status: Option<UserStatus>,
+    pub created_after: Option<NaiveDateTime>,
+    pub created_before: Option<NaiveDateTime>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// `LIKE` 的转义字符; 不使用反斜杠, 以免受 `NO_BACKSLASH_ESCAPES` 模式影响
+const LIKE_ESCAPE: char = '!';
+
+/// 转义 `LIKE` 模式中的通配符, 使输入按字面匹配
+fn escape_like(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '%' | '_') || c == LIKE_ESCAPE {
+            escaped.push(LIKE_ESCAPE);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl UserRepository for Pool {
+    fn create<'a>(&'a self, user: NewUser<'a>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome> {
+        Box::pin(async move {
+            // 用户与审计日志在同一事务中写入, 任一失败则都不生效
+            let mut tx = self.begin().await?;
+            if max_users.is_some() {
+                lock_quota(&mut tx).await?;
+            }
+            if exceeds_quota(&mut tx, 1, max_users).await? {
+                return Ok(WriteOutcome::QuotaExceeded);
+            }
+            let inserted = sqlx::query("INSERT INTO user (name, email, password_hash) VALUES (?,?,?)")
+                .bind(user.name)
+                .bind(user.email)
+                .bind(user.password_hash)
+                .execute(&mut *tx)
+                .await
+                .map(|_| WriteOutcome::Created);
+            let outcome = unique_violation_as_outcome(inserted)?;
+            if outcome != WriteOutcome::Created {
+                return Ok(outcome);
+            }
+            sqlx::query("INSERT INTO audit_log (actor_id, action, target) VALUES (NULL, 'user.create', ?)")
+                .bind(user.name)
+                .execute(&mut *tx)
+                .await?;
+            if let Some(key) = user.idempotency_key {
+                // 同一个键的并发重试会在插入用户时因用户名重复而失败
+                sqlx::query("INSERT INTO idempotency_key (idem_key, user_id, name) SELECT ?, id, name FROM user WHERE name=?")
+                    .bind(key)
+                    .bind(user.name)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok(WriteOutcome::Created)
+        })
+    }
+
+    fn create_many<'a>(&'a self, users: Vec<NewUser<'a>>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome> {
+        Box::pin(async move {
+            if users.is_empty() {
+                return Ok(WriteOutcome::Created);
+            }
+            let mut tx = self.begin().await?;
+            if max_users.is_some() {
+                lock_quota(&mut tx).await?;
+            }
+            if exceeds_quota(&mut tx, users.len() as i64, max_users).await? {
+                return Ok(WriteOutcome::QuotaExceeded);
+            }
+            let mut insert = sqlx::QueryBuilder::<Driver>::new("INSERT INTO user (name, email, password_hash) ");
+            insert.push_values(&users, |mut row, user| {
+                row.push_bind(user.name)
+                    .push_bind(user.email)
+                    .push_bind(user.password_hash.as_str());
+            });
+            let inserted = insert.build()
+                .execute(&mut *tx)
+                .await
+                .map(|_| WriteOutcome::Created);
+            let outcome = unique_violation_as_outcome(inserted)?;
+            if outcome != WriteOutcome::Created {
+                return Ok(outcome);
+            }
+            let mut audit = sqlx::QueryBuilder::<Driver>::new("INSERT INTO audit_log (actor_id, action, target) ");
+            audit.push_values(&users, |mut row, user| {
+                row.push("NULL")
+                    .push("'user.create'")
+                    .push_bind(user.name);
+            });
+            audit.build().execute(&mut *tx).await?;
+            tx.commit().await?;
+            Ok(WriteOutcome::Created)
+        })
+    }
+
+    fn upsert<'a>(&'a self, user: UpsertUser<'a>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome> {
+        Box::pin(async move {
+            let mut tx = self.begin().await?;
+            if max_users.is_some() {
+                lock_quota(&mut tx).await?;
+                // 更新未删除的用户不改变用户数
+                let existing: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user WHERE name=? AND deleted_at IS NULL")
+                    .bind(user.name)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                if exceeds_quota(&mut tx, if existing == 0 { 1 } else { 0 }, max_users).await? {
+                    return Ok(WriteOutcome::QuotaExceeded);
+                }
+            }
+            // MySQL 的 affected rows: 插入为 1, 更新为 2; 
+            // 散列使用随机盐, 更新时口令散列必然变化, 因此不会出现 0
+            let result = sqlx::query(
+                    "INSERT INTO user (name, password_hash, role) VALUES (?, ?, COALESCE(?, 'user')) \
+                    ON DUPLICATE KEY UPDATE \
+                    password_hash = VALUES(password_hash), \
+                    tokens_valid_after = ?, \
+                    role = COALESCE(?, role), \
+                    deleted_at = NULL"
+                )
+                .bind(user.name)
+                .bind(user.password_hash)
+                .bind(user.role)
+                .bind(chrono::Utc::now().naive_utc())
+                .bind(user.role)
+                .execute(&mut *tx)
+                .await?;
+            let outcome = if result.rows_affected() == 1 { WriteOutcome::Created } else { WriteOutcome::Changed };
+            sqlx::query("INSERT INTO audit_log (actor_id, action, target) VALUES (NULL, ?, ?)")
+                .bind(if outcome == WriteOutcome::Created { "user.create" } else { "user.update" })
+                .bind(user.name)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(outcome)
+        })
+    }
+
+    fn update<'a>(&'a self, id: UserId, changes: UserChanges<'a>) -> RepoFuture<'a, WriteOutcome> {
+        Box::pin(async move {
+            let query = match changes {
+                UserChanges { name: Some(name), password_hash: Some(password_hash) } => {
+                    sqlx::query("UPDATE user SET name=?, password_hash=?, tokens_valid_after=? WHERE id=? AND deleted_at IS NULL")
+                        .bind(name)
+                        .bind(password_hash)
+                        .bind(chrono::Utc::now().naive_utc())
+                        .bind(id)
+                }
+                UserChanges { name: Some(name), password_hash: None } => {
+                    sqlx::query("UPDATE user SET name=? WHERE id=? AND deleted_at IS NULL")
+                        .bind(name)
+                        .bind(id)
+                }
+                UserChanges { name: None, password_hash: Some(password_hash) } => {
+                    sqlx::query("UPDATE user SET password_hash=?, tokens_valid_after=? WHERE id=? AND deleted_at IS NULL")
+                        .bind(password_hash)
+                        .bind(chrono::Utc::now().naive_utc())
+                        .bind(id)
+                }
+                UserChanges { name: None, password_hash: None } => {
+                    let found = fetch_user(self, id).await?.is_some();
+                    return Ok(if found { WriteOutcome::Changed } else { WriteOutcome::NotFound });
+                }
+            };
+            let updated = query.execute(self)
+                .await
+                .map(|result| match result.rows_affected() {
+                    0 => WriteOutcome::NotFound,
+                    _ => WriteOutcome::Changed,
+                });
+            unique_violation_as_outcome(updated)
+        })
+    }
+
+    fn delete(&self, id: UserId) -> RepoFuture<'_, WriteOutcome> {
+        Box::pin(async move {
+            let mut tx = self.begin().await?;
+            let result = sqlx::query("UPDATE user SET deleted_at=? WHERE id=? AND deleted_at IS NULL")
+                .bind(chrono::Utc::now().naive_utc())
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            if result.rows_affected() == 0 {
+                return Ok(WriteOutcome::NotFound);
+            }
+            sqlx::query("DELETE FROM refresh_token WHERE user_id=?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(WriteOutcome::Changed)
+        })
+    }
+
+    fn restore(&self, id: UserId, max_users: Option<i64>) -> RepoFuture<'_, WriteOutcome> {
+        Box::pin(async move {
+            let mut tx = self.begin().await?;
+            if max_users.is_some() {
+                lock_quota(&mut tx).await?;
+            }
+            let result = sqlx::query("UPDATE user SET deleted_at=NULL WHERE id=? AND deleted_at IS NOT NULL")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            if result.rows_affected() == 0 {
+                return Ok(WriteOutcome::NotFound);
+            }
+            // 统计结果已包含刚恢复的用户
+            if exceeds_quota(&mut tx, 0, max_users).await? {
+                return Ok(WriteOutcome::QuotaExceeded);
+            }
+            tx.commit().await?;
+            Ok(WriteOutcome::Changed)
+        })
+    }
+
+    fn find_by_id(&self, id: UserId) -> RepoFuture<'_, Option<User>> {
+        Box::pin(fetch_user(self, id))
+    }
+
+    fn find_by_name<'a>(&'a self, name: &'a str) -> RepoFuture<'a, Option<User>> {
+        Box::pin(fetch_user_by_name(self, name))
+    }
+
+    fn find_by_email<'a>(&'a self, email: &'a str) -> RepoFuture<'a, Option<User>> {
+        Box::pin(
+            sqlx::query_as::<_, User>("SELECT id, name, email, password_hash, role, status, created_at, updated_at, last_login_at, failed_login_count, locked_until FROM user WHERE email=? AND deleted_at IS NULL")
+                .bind(email)
+                .fetch_optional(self)
+        )
+    }
+
+    fn record_failed_login(&self, id: UserId, threshold: i32, locked_until: NaiveDateTime) -> RepoFuture<'_, ()> {
+        Box::pin(async move {
+            // 两个赋值都读取更新前的 failed_login_count
+            sqlx::query(
+                    "UPDATE user SET \
+                    locked_until = IF(failed_login_count + 1 >= ?, ?, locked_until), \
+                    failed_login_count = IF(failed_login_count + 1 >= ?, 0, failed_login_count + 1), \
+                    updated_at = updated_at \
+                    WHERE id=?"
+                )
+                .bind(threshold)
+                .bind(locked_until)
+                .bind(threshold)
+                .bind(id)
+                .execute(self)
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn reset_failed_logins(&self, id: UserId) -> RepoFuture<'_, ()> {
+        Box::pin(async move {
+            sqlx::query("UPDATE user SET failed_login_count=0, locked_until=NULL, updated_at=updated_at WHERE id=?")
+                .bind(id)
+                .execute(self)
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn record_login(&self, id: UserId, at: NaiveDateTime) -> RepoFuture<'_, ()> {
+        Box::pin(async move {
+            // 显式保留 updated_at
+            sqlx::query("UPDATE user SET last_login_at=?, updated_at=updated_at WHERE id=?")
+                .bind(at)
+                .bind(id)
+                .execute(self)
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn replace_password_hash(&self, id: UserId, password_hash: String) -> RepoFuture<'_, ()> {
+        Box::pin(async move {
+            sqlx::query("UPDATE user SET password_hash=? WHERE id=? AND deleted_at IS NULL")
+                .bind(password_hash)
+                .bind(id)
+                .execute(self)
+                .await
+                .map(|_| ())
+        })
+    }
+
+    fn list<'a>(&'a self, filter: &'a UserFilter) -> RepoFuture<'a, (Vec<User>, i64)> {
+        Box::pin(async move {
+            // 未指定的一端以 NULL 绑定, 由 COALESCE 退化为恒真条件
+            let UserFilter { name_prefix, status, created_after, created_before, limit, offset } = filter;
+            let total;
+            let query;
+            match name_prefix {
+                Some(prefix) => {
+                    let pattern = escape_like(prefix);
+                    total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM user WHERE name LIKE CONCAT(?, '%') ESCAPE '!' AND deleted_at IS NULL AND status = COALESCE(?, status) AND created_at >= COALESCE(?, created_at) AND created_at <= COALESCE(?, created_at)")
+                        .bind(&pattern)
+                        .bind(*status)
+                        .bind(*created_after)
+                        .bind(*created_before)
+                        .fetch_one(self)
+                        .await?;
+                    query = sqlx::query_as::<_, User>("SELECT id, name, email, password_hash, role, status, created_at, updated_at, last_login_at, failed_login_count, locked_until FROM user WHERE name LIKE CONCAT(?, '%') ESCAPE '!' AND deleted_at IS NULL AND status = COALESCE(?, status) AND created_at >= COALESCE(?, created_at) AND created_at <= COALESCE(?, created_at) ORDER BY id LIMIT ? OFFSET ?")
+                        .bind(pattern)
+                        .bind(*status)
+                        .bind(*created_after)
+                        .bind(*created_before)
+                        .bind(i64::from(*limit))
+                        .bind(i64::from(*offset))
+                }
+                None => {
+                    total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM user WHERE deleted_at IS NULL AND status = COALESCE(?, status) AND created_at >= COALESCE(?, created_at) AND created_at <= COALESCE(?, created_at)")
+                        .bind(*status)
+                        .bind(*created_after)
+                        .bind(*created_before)
+                        .fetch_one(self)
+                        .await?;
+                    query = sqlx::query_as::<_, User>("SELECT id, name, email, password_hash, role, status, created_at, updated_at, last_login_at, failed_login_count, locked_until FROM user WHERE deleted_at IS NULL AND status = COALESCE(?, status) AND created_at >= COALESCE(?, created_at) AND created_at <= COALESCE(?, created_at) ORDER BY id LIMIT ? OFFSET ?")
+                        .bind(*status)
+                        .bind(*created_after)
+                        .bind(*created_before)
+                        .bind(i64::from(*limit))
+                        .bind(i64::from(*offset))
+                }
+            }
+            Ok((query.fetch_all(self).await?, total))
+        })
+    }
+
+
+    fn find_taken<'a>(&'a self, names: &'a [&'a str], emails: &'a [&'a str]) -> RepoFuture<'a, Vec<(String, Option<String>)>> {
+        Box::pin(async move {
+            if names.is_empty() && emails.is_empty() {
+                return Ok(Vec::new());
+            }
+            let mut select = sqlx::QueryBuilder::<Driver>::new("SELECT name, email FROM user WHERE name IN (");
+            // `IN ()` 不是合法的 SQL, 没有用户名时以 NULL 占位, 不匹配任何行
+            if names.is_empty() {
+                select.push("NULL");
+            }
+            let mut separated = select.separated(", ");
+            for name in names {
+                separated.push_bind(*name);
+            }
+            select.push(")");
+            if !emails.is_empty() {
+                select.push(" OR email IN (");
+                let mut separated = select.separated(", ");
+                for email in emails {
+                    separated.push_bind(*email);
+                }
+                select.push(")");
+            }
+            select.build_query_as::<(String, Option<String>)>().fetch_all(self).await
+        })
+    }
+
+    fn count(&self) -> RepoFuture<'_, i64> {
+        Box::pin(
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM user WHERE deleted_at IS NULL")
+                .fetch_one(self)
+        )
+    }
+
+    fn export(&self) -> BoxStream<'_, Result<UserSummary, sqlx::Error>> {
+        sqlx::query_as::<_, UserSummary>("SELECT id, name, created_at FROM user WHERE deleted_at IS NULL ORDER BY id")
+            .fetch(self)
+    }
+
+    fn find_idempotency_key<'a>(&'a self, key: &'a str, expired_before: NaiveDateTime) -> RepoFuture<'a, Option<String>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM idempotency_key WHERE created_at < ?")
+                .bind(expired_before)
+                .execute(self)
+                .await?;
+            sqlx::query_scalar("SELECT name FROM idempotency_key WHERE idem_key=?")
+                .bind(key)
+                .fetch_optional(self)
+                .await
+        })
+    }
+}
+
+/// 保存在内存中的用户存储, 供测试使用, 行为与 [`Pool`] 的实现一致: 
+/// 用户名与邮箱不区分大小写地唯一, 软删除的用户仍占用用户名与邮箱
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemoryUsers {
+    inner: std::sync::Mutex<InMemoryState>,
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct InMemoryState {
+    /// 用户及其是否已软删除
+    users: Vec<(User, bool)>,
+    /// 幂等键到用户名与写入时间的映射
+    idempotency_keys: std::collections::HashMap<String, (String, NaiveDateTime)>,
+}
+
+#[cfg(test)]
+impl InMemoryState {
+    fn active(&self) -> impl Iterator<Item = &User> {
+        self.users.iter().filter(|(_, deleted)| !deleted).map(|(user, _)| user)
+    }
+
+    fn active_mut(&mut self, id: UserId) -> Option<&mut User> {
+        self.users.iter_mut().find(|(user, deleted)| user.id == id && !*deleted).map(|(user, _)| user)
+    }
+
+    fn exceeds_quota(&self, adding: usize, max_users: Option<i64>) -> bool {
+        max_users.is_some_and(|max| (self.active().count() + adding) as i64 > max)
+    }
+
+    /// 与已有用户(不包括 `except`)冲突时返回对应的结果
+    fn conflict(&self, name: Option<&str>, email: Option<&str>, except: Option<UserId>) -> Option<WriteOutcome> {
+        let others = || self.users.iter().map(|(user, _)| user).filter(move |user| Some(user.id) != except);
+        if let Some(name) = name {
+            if others().any(|user| user.name.eq_ignore_ascii_case(name)) {
+                return Some(WriteOutcome::NameTaken);
+            }
+        }
+        if let Some(email) = email {
+            if others().any(|user| user.email.as_deref().is_some_and(|taken| taken.eq_ignore_ascii_case(email))) {
+                return Some(WriteOutcome::EmailTaken);
+            }
+        }
+        None
+    }
+
+    fn insert(&mut self, name: &str, email: Option<&str>, password_hash: String, role: &str) {
+        let now = chrono::Utc::now().naive_utc();
+        let id = UserId(self.users.len() as i32 + 1);
+        self.users.push((User {
+            id,
+            name: name.to_string(),
+            email: email.map(str::to_string),
+            password_hash,
+            role: role.to_string(),
+            status: UserStatus::Active,
+            created_at: now,
+            updated_at: now,
+            last_login_at: None,
+            failed_login_count: 0,
+            locked_until: None,
+        }, false));
+    }
+}
+
+#[cfg(test)]
+impl InMemoryUsers {
+    fn with_state<T: Send + 'static>(&self, f: impl FnOnce(&mut InMemoryState) -> T) -> RepoFuture<'_, T> {
+        let result = f(&mut *self.inner.lock().expect("in-memory users lock poisoned"));
+        Box::pin(std::future::ready(Ok(result)))
+    }
+
+    /// 修改用户状态; 仓库接口没有对应的方法, 供测试构造未激活或已停用的用户
+    pub fn set_status(&self, id: UserId, status: UserStatus) {
+        let mut state = self.inner.lock().expect("in-memory users lock poisoned");
+        if let Some(user) = state.active_mut(id) {
+            user.status = status;
+        }
+    }
+}
+
+#[cfg(test)]
+impl UserRepository for InMemoryUsers {
+    fn create<'a>(&'a self, user: NewUser<'a>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome> {
+        self.with_state(move |state| {
+            if state.exceeds_quota(1, max_users) {
+                return WriteOutcome::QuotaExceeded;
+            }
+            if let Some(conflict) = state.conflict(Some(user.name), user.email, None) {
+                return conflict;
+            }
+            state.insert(user.name, user.email, user.password_hash, "user");
+            if let Some(key) = user.idempotency_key {
+                state.idempotency_keys.insert(key.to_string(), (user.name.to_string(), chrono::Utc::now().naive_utc()));
+            }
+            WriteOutcome::Created
+        })
+    }
+
+    fn create_many<'a>(&'a self, users: Vec<NewUser<'a>>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome> {
+        self.with_state(move |state| {
+            if state.exceeds_quota(users.len(), max_users) {
+                return WriteOutcome::QuotaExceeded;
+            }
+            for user in &users {
+                if let Some(conflict) = state.conflict(Some(user.name), user.email, None) {
+                    return conflict;
+                }
+            }
+            for user in users {
+                state.insert(user.name, user.email, user.password_hash, "user");
+            }
+            WriteOutcome::Created
+        })
+    }
+
+    fn upsert<'a>(&'a self, user: UpsertUser<'a>, max_users: Option<i64>) -> RepoFuture<'a, WriteOutcome> {
+        self.with_state(move |state| {
+            let existing = state.users.iter().position(|(stored, _)| stored.name.eq_ignore_ascii_case(user.name));
+            let Some(index) = existing else {
+                if state.exceeds_quota(1, max_users) {
+                    return WriteOutcome::QuotaExceeded;
+                }
+                state.insert(user.name, None, user.password_hash, user.role.unwrap_or("user"));
+                return WriteOutcome::Created;
+            };
+            if state.users[index].1 && state.exceeds_quota(1, max_users) {
+                return WriteOutcome::QuotaExceeded;
+            }
+            let (stored, deleted) = &mut state.users[index];
+            stored.password_hash = user.password_hash;
+            if let Some(role) = user.role {
+                stored.role = role.to_string();
+            }
+            *deleted = false;
+            WriteOutcome::Changed
+        })
+    }
+
+    fn update<'a>(&'a self, id: UserId, changes: UserChanges<'a>) -> RepoFuture<'a, WriteOutcome> {
+        self.with_state(move |state| {
+            if !state.active().any(|user| user.id == id) {
+                return WriteOutcome::NotFound;
+            }
+            if let Some(conflict) = state.conflict(changes.name, None, Some(id)) {
+                return conflict;
+            }
+            let (user, _) = state.users.iter_mut().find(|(user, _)| user.id == id).expect("user exists");
+            if let Some(name) = changes.name {
+                user.name = name.to_string();
+            }
+            if let Some(password_hash) = changes.password_hash {
+                user.password_hash = password_hash;
+            }
+            WriteOutcome::Changed
+        })
+    }
+
+    fn delete(&self, id: UserId) -> RepoFuture<'_, WriteOutcome> {
+        self.with_state(move |state| {
+            match state.users.iter_mut().find(|(user, deleted)| user.id == id && !*deleted) {
+                Some((_, deleted)) => {
+                    *deleted = true;
+                    WriteOutcome::Changed
+                }
+                None => WriteOutcome::NotFound,
+            }
+        })
+    }
+
+    fn restore(&self, id: UserId, max_users: Option<i64>) -> RepoFuture<'_, WriteOutcome> {
+        self.with_state(move |state| {
+            let Some(index) = state.users.iter().position(|(user, deleted)| user.id == id && *deleted) else {
+                return WriteOutcome::NotFound;
+            };
+            if state.exceeds_quota(1, max_users) {
+                return WriteOutcome::QuotaExceeded;
+            }
+            state.users[index].1 = false;
+            WriteOutcome::Changed
+        })
+    }
+
+    fn find_by_id(&self, id: UserId) -> RepoFuture<'_, Option<User>> {
+        self.with_state(move |state| state.active().find(|user| user.id == id).cloned())
+    }
+
+    fn find_by_name<'a>(&'a self, name: &'a str) -> RepoFuture<'a, Option<User>> {
+        let name = name.to_string();
+        self.with_state(move |state| state.active().find(|user| user.name.eq_ignore_ascii_case(&name)).cloned())
+    }
+
+    fn find_by_email<'a>(&'a self, email: &'a str) -> RepoFuture<'a, Option<User>> {
+        let email = email.to_string();
+        self.with_state(move |state| {
+            state.active()
+                .find(|user| user.email.as_deref().is_some_and(|stored| stored.eq_ignore_ascii_case(&email)))
+                .cloned()
+        })
+    }
+
+    fn record_failed_login(&self, id: UserId, threshold: i32, locked_until: NaiveDateTime) -> RepoFuture<'_, ()> {
+        self.with_state(move |state| {
+            if let Some(user) = state.active_mut(id) {
+                if user.failed_login_count + 1 >= threshold {
+                    user.failed_login_count = 0;
+                    user.locked_until = Some(locked_until);
+                } else {
+                    user.failed_login_count += 1;
+                }
+            }
+        })
+    }
+
+    fn reset_failed_logins(&self, id: UserId) -> RepoFuture<'_, ()> {
+        self.with_state(move |state| {
+            if let Some(user) = state.active_mut(id) {
+                user.failed_login_count = 0;
+                user.locked_until = None;
+            }
+        })
+    }
+
+    fn record_login(&self, id: UserId, at: NaiveDateTime) -> RepoFuture<'_, ()> {
+        self.with_state(move |state| {
+            if let Some(user) = state.active_mut(id) {
+                user.last_login_at = Some(at);
+            }
+        })
+    }
+
+    fn replace_password_hash(&self, id: UserId, password_hash: String) -> RepoFuture<'_, ()> {
+        self.with_state(move |state| {
+            if let Some(user) = state.active_mut(id) {
+                user.password_hash = password_hash;
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, filter: &'a UserFilter) -> RepoFuture<'a, (Vec<User>, i64)> {
+        let UserFilter { name_prefix, status, created_after, created_before, limit, offset } = filter;
+        let name_prefix = name_prefix.as_ref().map(|prefix| prefix.to_lowercase());
+        let (status, created_after, created_before, limit, offset) = (*status, *created_after, *created_before, *limit, *offset);
+        self.with_state(move |state| {
+            let mut matched: Vec<User> = state.active()
+                .filter(|user| name_prefix.as_ref().is_none_or(|prefix| user.name.to_lowercase().starts_with(prefix)))
+                .filter(|user| status.is_none_or(|status| user.status == status))
+                .filter(|user| created_after.is_none_or(|after| user.created_at >= after))
+                .filter(|user| created_before.is_none_or(|before| user.created_at <= before))
+                .cloned()
+                .collect();
+            matched.sort_by_key(|user| user.id.0);
+            let total = matched.len() as i64;
+            let page = matched.into_iter().skip(offset as usize).take(limit as usize).collect();
+            (page, total)
+        })
+    }
+
+    fn find_taken<'a>(&'a self, names: &'a [&'a str], emails: &'a [&'a str]) -> RepoFuture<'a, Vec<(String, Option<String>)>> {
+        let names: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+        let emails: Vec<String> = emails.iter().map(|email| email.to_lowercase()).collect();
+        self.with_state(move |state| {
+            state.users.iter()
+                .map(|(user, _)| user)
+                .filter(|user| {
+                    names.contains(&user.name.to_lowercase())
+                        || user.email.as_ref().is_some_and(|email| emails.contains(&email.to_lowercase()))
+                })
+                .map(|user| (user.name.clone(), user.email.clone()))
+                .collect()
+        })
+    }
+
+    fn count(&self) -> RepoFuture<'_, i64> {
+        self.with_state(|state| state.active().count() as i64)
+    }
+
+    fn export(&self) -> BoxStream<'_, Result<UserSummary, sqlx::Error>> {
+        let rows: Vec<_> = self.inner.lock().expect("in-memory users lock poisoned")
+            .active()
+            .map(|user| Ok((user.id, user.name.clone(), user.created_at)))
+            .collect();
+        Box::pin(futures_util::stream::iter(rows))
+    }
+
+    fn find_idempotency_key<'a>(&'a self, key: &'a str, expired_before: NaiveDateTime) -> RepoFuture<'a, Option<String>> {
+        let key = key.to_string();
+        self.with_state(move |state| {
+            state.idempotency_keys.retain(|_, (_, created_at)| *created_at >= expired_before);
+            state.idempotency_keys.get(&key).map(|(name, _)| name.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_like_escapes_wildcards_and_escape_char() {
+        assert_eq!(escape_like("alice"), "alice");
+        assert_eq!(escape_like("a%b_c"), "a!%b!_c");
+        assert_eq!(escape_like("wow!"), "wow!!");
+        assert_eq!(escape_like(r"back\slash"), r"back\slash");
+    }
+
+    // 审计日志与用户在同一事务中写入, 审计日志写入失败时用户同样不会创建
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn create_is_rolled_back_when_audit_log_fails(pool: Pool) {
+        sqlx::query("DROP TABLE audit_log").execute(&pool).await.unwrap();
+        let user = NewUser { name: "alice", email: None, password_hash: "hash".to_string(), idempotency_key: Some("create-alice") };
+        assert!(pool.create(user, None).await.is_err());
+        assert!(pool.find_by_name("alice").await.unwrap().is_none());
+        let cutoff = chrono::Utc::now().naive_utc() - chrono::Duration::days(1);
+        assert!(pool.find_idempotency_key("create-alice", cutoff).await.unwrap().is_none());
+    }
+}
@@ -16,98 +16,1515 @@
 *   along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use axum::{extract::{Query, State}, http::StatusCode, routing::post, Json, Router};
-use sqlx::{prelude::*, types::chrono, MySqlPool};
+use std::{collections::HashSet, fmt::Display, str::FromStr, sync::{Arc, OnceLock}};
+
+use axum::{body::Body, extract::{DefaultBodyLimit, Path, Query, State}, http::{header, HeaderMap, StatusCode}, middleware, response::IntoResponse, routing::{get, post, put}, Json, Router};
+use futures_util::TryStreamExt;
+use sqlx::types::chrono;
 use serde::{Deserialize, Serialize};
-use crate::util::{error::internal_error, password::{PasswordProperties, PasswordWithRandomSalt, PasswordWithSalt, StringPassword}};
+use utoipa::{IntoParams, ToSchema};
+use crate::database::{Driver, Pool};
+use crate::model::repository::{retry_read, NewUser, UpsertUser, UserChanges, UserFilter, UserRepository, WriteOutcome};
+use crate::server::{client_ip::ClientAddr, rate_limit::{rate_limit, RateLimiter}, state::AppState};
+use crate::server::auth::{role, scope, verify_credentials, AuthConfig, AuthError, Claims, Identity, OptionalClaims, RequireRole, RequireScope};
+use crate::util::{error::{database_error, internal_error, ApiError, ErrorBody}, json::ApiJson, password::{hasher, HashAlgorithm, PasswordError, PasswordHasher, PasswordProperties, PasswordWithRandomSalt, PasswordWithSalt, StringPassword, WeakPassword}};
+
+/// 用户 id, 与其他整数 id 区分; 序列化与数据库中均为普通整数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(transparent)]
+#[sqlx(transparent)]
+pub struct UserId(pub i32);
+
+impl Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for UserId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// 用户的生命周期状态, 只有 `Active` 的用户可以登录
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Suspended,
+    Pending,
+}
+
+impl UserStatus {
+    /// 数据库与 JSON 中的取值
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UserStatus::Active => "active",
+            UserStatus::Suspended => "suspended",
+            UserStatus::Pending => "pending",
+        }
+    }
+}
+
+impl FromStr for UserStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(UserStatus::Active),
+            "suspended" => Ok(UserStatus::Suspended),
+            "pending" => Ok(UserStatus::Pending),
+            _ => Err(format!("unknown user status `{s}`")),
+        }
+    }
+}
+
+// status 列是 VARCHAR, 而 `derive(sqlx::Type)` 为枚举生成的 MySQL 类型只兼容 ENUM 列, 
+// 解码时会报类型不匹配, 因此按字符串编码与解码
+impl sqlx::Type<Driver> for UserStatus {
+    fn type_info() -> <Driver as sqlx::Database>::TypeInfo {
+        <str as sqlx::Type<Driver>>::type_info()
+    }
 
-// 用户数据库模型
-#[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
+    fn compatible(ty: &<Driver as sqlx::Database>::TypeInfo) -> bool {
+        <str as sqlx::Type<Driver>>::compatible(ty)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Driver> for UserStatus {
+    fn decode(value: <Driver as sqlx::Database>::ValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        Ok(<&str as sqlx::Decode<'r, Driver>>::decode(value)?.parse()?)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Driver> for UserStatus {
+    fn encode_by_ref(
+        &self, buf: &mut <Driver as sqlx::Database>::ArgumentBuffer<'q>
+    ) -> Result<sqlx::encode::IsNull, sqlx::error::BoxDynError> {
+        <&str as sqlx::Encode<'q, Driver>>::encode_by_ref(&self.as_str(), buf)
+    }
+}
+
+// 用户数据库模型, 字段与 user 表的列同名, 查询时通过 `query_as` 直接映射; 
+// 查询只返回未删除的用户, 因此不读取 deleted_at
+// 包含口令散列, 因此不实现 `Serialize` 与 `Deserialize`, 返回给客户端时先转换为 `UserPublic`
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct User {
-    pub id: i32,
+    pub id: UserId,
     pub name: String,
+    pub email: Option<String>,
     pub password_hash: String,
-    pub created_at: chrono::NaiveDateTime
+    pub role: String,
+    pub status: UserStatus,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    /// 最近一次成功登录的时间
+    pub last_login_at: Option<chrono::NaiveDateTime>,
+    /// 连续登录失败的次数, 成功登录或锁定后清零
+    pub failed_login_count: i32,
+    /// 账户锁定的截止时间
+    pub locked_until: Option<chrono::NaiveDateTime>,
+}
+
+// 对外公开的用户信息, 不包含口令散列; 字段名与前端一致使用 camelCase
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserPublic {
+    pub id: UserId,
+    pub name: String,
+    pub email: Option<String>,
+    pub role: String,
+    pub status: UserStatus,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub last_login_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<User> for UserPublic {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            name: user.name,
+            email: user.email,
+            role: user.role,
+            status: user.status,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            last_login_at: user.last_login_at,
+        }
+    }
 }
 
-pub fn user_router() -> Router<MySqlPool> {
+impl UserPublic {
+    /// 未登录的调用者看不到邮箱与最近登录时间
+    pub fn redact_private(mut self) -> Self {
+        self.email = None;
+        self.last_login_at = None;
+        self
+    }
+}
+
+/// `limiter` 与 `/auth/authorize` 共用, 以免通过 `/users/verify-password` 绕过登录限流
+pub fn user_router(limiter: Arc<RateLimiter>) -> Router<AppState> {
     Router::new()
-        .route("/", post(create_user).get(query_user))
+        .route("/", post(create_user).get(query_user).put(update_user))
+        // 批量请求的体积远大于其他接口, 单独放宽请求体限制
+        .route(
+            "/batch", 
+            post(create_users_batch).layer(DefaultBodyLimit::max(MAX_BATCH_SIZE * 1024))
+        )
+        .route("/upsert", put(upsert_user))
+        .route(
+            "/verify-password", 
+            post(verify_user_password).layer(middleware::from_fn_with_state(limiter, rate_limit))
+        )
+        .route("/count", get(count_users))
+        .route("/export.csv", get(export_users_csv))
+        .route("/{id}", get(get_user).delete(delete_user))
+        .route("/{id}/restore", post(restore_user))
 }
 
 #[derive(Debug)]
 pub struct UserPasswordProperties;
 
-impl PasswordProperties for UserPasswordProperties {}
+impl PasswordProperties for UserPasswordProperties {
+    type Hasher = UserHasher;
+    const MIN_LEN: usize = 8;
+    const MIN_CHAR_CLASSES: usize = 2;
+}
 
 impl PasswordWithSalt for UserPasswordProperties {
     const COST: u32 = 12;
     const SALT: [u8; 16] = [3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5, 8, 9, 7, 9, 3];
+
+    fn cost() -> u32 {
+        password_hashing().cost
+    }
 }
 
 impl PasswordWithRandomSalt for UserPasswordProperties {
     const COST: u32 = <Self as PasswordWithSalt>::COST;
+
+    fn cost() -> u32 {
+        password_hashing().cost
+    }
 }
 
-type UserPassword = StringPassword<UserPasswordProperties>;
+/// bcrypt 允许的代价范围
+pub const BCRYPT_MIN_COST: u32 = 4;
+pub const BCRYPT_MAX_COST: u32 = 31;
+/// Argon2id 的默认迭代次数, 配合默认的 19 MiB 内存
+pub const ARGON2_DEFAULT_ITERATIONS: u32 = 2;
 
-#[derive(Debug, Deserialize)]
-struct CreateUserRequest {
+/// 新口令使用的散列算法与代价
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordHashing {
+    pub algorithm: HashAlgorithm,
+    /// bcrypt 的代价, 或 Argon2id 的迭代次数
+    pub cost: u32,
+}
+
+static PASSWORD_HASHING: OnceLock<PasswordHashing> = OnceLock::new();
+
+/// 设置用户口令的散列算法与代价, 只能在启动时设置一次; 
+/// 取值由 [`Config::password_hashing`](crate::config::Config::password_hashing) 校验
+pub fn set_password_hashing(hashing: PasswordHashing) -> anyhow::Result<()> {
+    PASSWORD_HASHING.set(hashing).map_err(|_| anyhow::anyhow!("password hashing is already set"))
+}
+
+/// 未设置时使用代价为 `COST` 的 bcrypt
+fn password_hashing() -> PasswordHashing {
+    PASSWORD_HASHING.get().copied().unwrap_or(PasswordHashing { 
+        algorithm: HashAlgorithm::Bcrypt, 
+        cost: <UserPasswordProperties as PasswordWithSalt>::COST,
+    })
+}
+
+/// 按 [`PasswordHashing`] 选择算法的散列; 校验时由散列的前缀判断算法, 切换算法后已有口令仍然可用, 
+/// 并在下次登录时重新散列
+/// 
+/// 无论选择哪种算法, 口令长度都按 bcrypt 限制, 使口令规则不随部署配置变化
+#[derive(Debug)]
+pub struct UserHasher;
+
+impl PasswordHasher for UserHasher {
+    const MAX_INPUT_BYTES: usize = <hasher::Bcrypt as PasswordHasher>::MAX_INPUT_BYTES;
+
+    fn hash(password: &str, cost: u32, salt: [u8; 16]) -> Result<String, PasswordError> {
+        match password_hashing().algorithm {
+            HashAlgorithm::Bcrypt => hasher::Bcrypt::hash(password, cost, salt),
+            HashAlgorithm::Argon2 => hasher::Argon2::hash(password, cost, salt),
+        }
+    }
+
+    fn is_current(hash: &str, cost: u32) -> bool {
+        match password_hashing().algorithm {
+            HashAlgorithm::Bcrypt => hasher::Bcrypt::is_current(hash, cost),
+            HashAlgorithm::Argon2 => hasher::Argon2::is_current(hash, cost),
+        }
+    }
+}
+
+pub(crate) type UserPassword = StringPassword<UserPasswordProperties>;
+
+/// 用户名长度限制(字符数)
+const USERNAME_MIN_LEN: usize = 3;
+const USERNAME_MAX_LEN: usize = 32;
+
+/// 经过校验的用户名, 只能由字母、数字及 `_`, `-`, `.` 组成
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Username(String);
+
+impl Username {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Username> for String {
+    fn from(name: Username) -> Self {
+        name.0
+    }
+}
+
+impl Display for Username {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidUsername {
+    TooShort { min: usize },
+    TooLong { max: usize },
+    InvalidCharacter(char),
+}
+
+impl Display for InvalidUsername {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidUsername::TooShort { min } => {
+                write!(f, "username must be at least {min} characters long")
+            }
+            InvalidUsername::TooLong { max } => {
+                write!(f, "username must be at most {max} characters long")
+            }
+            InvalidUsername::InvalidCharacter(c) => {
+                write!(f, "username contains invalid character {c:?}")
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Username {
+    type Error = InvalidUsername;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let len = name.chars().count();
+        if len < USERNAME_MIN_LEN {
+            return Err(InvalidUsername::TooShort { min: USERNAME_MIN_LEN });
+        }
+        if len > USERNAME_MAX_LEN {
+            return Err(InvalidUsername::TooLong { max: USERNAME_MAX_LEN });
+        }
+        if let Some(c) = name.chars().find(|&c| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))) {
+            return Err(InvalidUsername::InvalidCharacter(c));
+        }
+        Ok(Self(name))
+    }
+}
+
+/// 邮箱最大长度(字节), 与数据库列宽一致
+const EMAIL_MAX_LEN: usize = 254;
+
+/// 经过格式校验的邮箱: 恰好一个 `@`, 两侧非空, 域名中包含 `.`, 不含空白字符
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Email(String);
+
+impl Email {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+pub enum InvalidEmail {
+    TooLong { max: usize },
+    Malformed,
+}
+
+impl Display for InvalidEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidEmail::TooLong { max } => write!(f, "email must be at most {max} bytes long"),
+            InvalidEmail::Malformed => write!(f, "email is malformed"),
+        }
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = InvalidEmail;
+
+    fn try_from(email: String) -> Result<Self, Self::Error> {
+        if email.len() > EMAIL_MAX_LEN {
+            return Err(InvalidEmail::TooLong { max: EMAIL_MAX_LEN });
+        }
+        let well_formed = match email.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.split('.').count() >= 2
+                    && domain.split('.').all(|label| !label.is_empty())
+                    && !email.chars().any(char::is_whitespace)
+            }
+            None => false,
+        };
+        if !well_formed {
+            return Err(InvalidEmail::Malformed);
+        }
+        Ok(Self(email))
+    }
+}
+
+pub(crate) fn weak_password(err: WeakPassword) -> ApiError {
+    ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CreateUserRequest {
+    #[schema(value_type = String, min_length = 3, max_length = 32)]
+    name: Username,
+    /// 可选, 设置后可用于登录
+    #[schema(value_type = Option<String>, format = Email, max_length = 254)]
+    #[serde(default)]
+    email: Option<Email>,
+    #[schema(value_type = String, format = Password)]
+    password: UserPassword,
+}
+
+// 口令不应出现在日志中
+impl std::fmt::Debug for CreateUserRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CreateUserRequest")
+            .field("name", &self.name)
+            .field("email", &self.email)
+            .field("password", &format_args!("***"))
+            .finish()
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = CreateUserRequest,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "重试时携带相同的键, 不会重复创建用户"),
+    ),
+    responses(
+        (status = 200, description = "用户已创建", body = String),
+        (status = 403, description = "用户数已达 users.max_users 上限", body = ErrorBody),
+        (status = 409, description = "用户名或邮箱已被占用", body = ErrorBody),
+        (status = 415, description = "请求体不是 JSON", body = ErrorBody),
+        (status = 422, description = "用户名、邮箱或口令不符合要求", body = ErrorBody),
+        (status = 503, description = "数据库不可用; 不会自动重试, 写入可能已生效, 重试时请带上相同的 Idempotency-Key", body = ErrorBody),
+    )
+)]
+pub(crate) async fn create_user(
+    State(users): State<Arc<dyn UserRepository>>, State(config): State<UserConfig>, headers: HeaderMap, 
+    ApiJson(payload): ApiJson<CreateUserRequest>
+) -> Result<String, ApiError> {
+    let idempotency_key = idempotency_key(&headers)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(result) = replay_idempotent(users.as_ref(), key, &payload.name, config.idempotency_ttl).await? {
+            return Ok(result);
+        }
+    }
+    payload.password.check_strength().map_err(weak_password)?;
+    let password_hash = payload.password.hash_with_random_salt().map_err(internal_error)?;
+
+    let new_user = NewUser {
+        name: payload.name.as_str(),
+        email: payload.email.as_ref().map(Email::as_str),
+        password_hash,
+        idempotency_key: idempotency_key.as_deref(),
+    };
+    users.create(new_user, config.max_users)
+        .await
+        .map_err(database_error)
+        .and_then(write_outcome)?;
+
+    tracing::info!(name = %payload.name, "user created");
+    metrics::counter!("users_created_total").increment(1);
+    Ok("ok".to_string())
+}
+
+/// 幂等键的最大长度, 与数据库列宽一致
+const IDEMPOTENCY_KEY_MAX_LEN: usize = 255;
+
+/// 用户管理的配置, 见 [`Config::users`](crate::config::Config::users)
+#[derive(Debug, Clone)]
+pub struct UserConfig {
+    /// 未删除用户数的上限, 为 `None` 时不限制; 
+    /// 单个创建、批量创建、按用户名创建或恢复以及恢复软删除的用户都受此限制
+    pub max_users: Option<i64>,
+    /// 幂等键的有效期(秒)
+    pub idempotency_ttl: i64,
+}
+
+impl Default for UserConfig {
+    fn default() -> Self {
+        Self { 
+            max_users: None,
+            idempotency_ttl: 24 * 3600,
+        }
+    }
+}
+
+fn quota_exceeded() -> ApiError {
+    ApiError::new(StatusCode::FORBIDDEN, "user quota exceeded")
+}
+
+/// 写操作未生效时返回对应的错误: 用户不存在为 404, 用户名或邮箱冲突为 409, 超出配额为 403
+fn write_outcome(outcome: WriteOutcome) -> Result<WriteOutcome, ApiError> {
+    match outcome {
+        WriteOutcome::Created | WriteOutcome::Changed => Ok(outcome),
+        WriteOutcome::NotFound => Err(ApiError::new(StatusCode::NOT_FOUND, "user not found")),
+        WriteOutcome::NameTaken => Err(ApiError::new(StatusCode::CONFLICT, "username already taken")),
+        WriteOutcome::EmailTaken => Err(ApiError::new(StatusCode::CONFLICT, "email already taken")),
+        WriteOutcome::QuotaExceeded => Err(quota_exceeded()),
+    }
+}
+
+/// 读取 `Idempotency-Key` 请求头, 未提供时返回 `None`
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= IDEMPOTENCY_KEY_MAX_LEN => Ok(Some(key.to_string())),
+        _ => Err(ApiError::new(
+            StatusCode::BAD_REQUEST, 
+            format!("Idempotency-Key must be 1 to {IDEMPOTENCY_KEY_MAX_LEN} visible ASCII characters")
+        )),
+    }
+}
+
+/// 查找未过期的幂等键: 找到且对应同一用户名时返回首次请求的结果, 
+/// 键已用于其他请求时返回 `422`
+async fn replay_idempotent(
+    users: &dyn UserRepository, key: &str, name: &Username, ttl: i64
+) -> Result<Option<String>, ApiError> {
+    let cutoff = chrono::Utc::now().naive_utc() - ::chrono::Duration::seconds(ttl);
+    let stored = users.find_idempotency_key(key, cutoff).await.map_err(database_error)?;
+    match stored {
+        Some(stored) if stored == name.as_str() => Ok(Some("ok".to_string())),
+        Some(_) => Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY, 
+            "idempotency key was already used for a different request"
+        )),
+        None => Ok(None),
+    }
+}
+
+/// 单次批量创建的最大用户数
+const MAX_BATCH_SIZE: usize = 500;
+
+/// 批量创建中单个用户的结果
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum BatchCreateStatus {
+    Created,
+    /// 用户名或邮箱已被占用, 包括与同一批次中靠前的条目重复
+    Conflict { reason: &'static str },
+    /// 口令强度不足
+    Invalid { reason: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchCreateResult {
     name: String,
+    #[serde(flatten)]
+    status: BatchCreateStatus,
+}
+
+/// 批量创建用户, 所有可创建的用户在同一事务中以一条 INSERT 写入, 
+/// 冲突或口令不合格的条目被跳过, 并在对应位置的结果中说明原因; 需要 `users:write`
+async fn create_users_batch(
+    State(users): State<Arc<dyn UserRepository>>, State(config): State<UserConfig>, 
+    _writer: RequireScope<scope::UsersWrite>, ApiJson(payload): ApiJson<Vec<CreateUserRequest>>
+) -> Result<Json<Vec<BatchCreateResult>>, ApiError> {
+    if payload.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY, 
+            format!("at most {MAX_BATCH_SIZE} users can be created at once")
+        ));
+    }
+    if payload.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+
+    // 数据库默认的排序规则不区分大小写, 比较时统一转为小写
+    let names: Vec<&str> = payload.iter().map(|request| request.name.as_str()).collect();
+    let emails: Vec<&str> = payload.iter().filter_map(|request| request.email.as_ref()).map(Email::as_str).collect();
+    let mut taken_names = HashSet::new();
+    let mut taken_emails = HashSet::new();
+    for (name, email) in users.find_taken(&names, &emails).await.map_err(database_error)? {
+        taken_names.insert(name.to_lowercase());
+        if let Some(email) = email {
+            taken_emails.insert(email.to_lowercase());
+        }
+    }
+
+    let mut results = Vec::with_capacity(payload.len());
+    let mut accepted = Vec::new();
+    for request in payload {
+        let name = request.name.as_str().to_lowercase();
+        let email = request.email.as_ref().map(|email| email.as_str().to_lowercase());
+        let status = if let Err(err) = request.password.check_strength() {
+            BatchCreateStatus::Invalid { reason: err.to_string() }
+        } else if taken_names.contains(&name) {
+            BatchCreateStatus::Conflict { reason: "username already taken" }
+        } else if email.as_ref().is_some_and(|email| taken_emails.contains(email)) {
+            BatchCreateStatus::Conflict { reason: "email already taken" }
+        } else {
+            taken_names.insert(name);
+            taken_emails.extend(email);
+            BatchCreateStatus::Created
+        };
+        let created = matches!(status, BatchCreateStatus::Created);
+        results.push(BatchCreateResult { name: request.name.to_string(), status });
+        if created {
+            accepted.push(request);
+        }
+    }
+    if accepted.is_empty() {
+        return Ok(Json(results));
+    }
+
+    // 散列开销较大, 放到阻塞线程池中执行
+    let rows = tokio::task::spawn_blocking(move || {
+        accepted.into_iter()
+            .map(|request| request.password.hash_with_random_salt().map(|hash| (request, hash)))
+            .collect::<Result<Vec<_>, _>>()
+    }).await.map_err(internal_error)?.map_err(internal_error)?;
+
+    let count = rows.len();
+    let new_users = rows.iter()
+        .map(|(request, hash)| NewUser {
+            name: request.name.as_str(),
+            email: request.email.as_ref().map(Email::as_str),
+            password_hash: hash.clone(),
+            idempotency_key: None,
+        })
+        .collect();
+    match users.create_many(new_users, config.max_users).await.map_err(database_error)? {
+        // 查询与插入之间被其他请求抢先创建
+        WriteOutcome::NameTaken | WriteOutcome::EmailTaken => {
+            return Err(ApiError::new(StatusCode::CONFLICT, "users were created concurrently, retry the request"));
+        }
+        outcome => write_outcome(outcome)?,
+    };
+
+    tracing::info!(count, "users created in batch");
+    metrics::counter!("users_created_total").increment(count as u64);
+    Ok(Json(results))
+}
+
+/// 可以赋予用户的角色
+const ROLES: [&str; 2] = ["user", "admin"];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpsertUserRequest {
+    name: Username,
     password: UserPassword,
+    /// 未指定时, 新用户为 `user`, 已有用户保持原角色
+    role: Option<String>,
+}
+
+impl std::fmt::Debug for UpsertUserRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpsertUserRequest")
+            .field("name", &self.name)
+            .field("password", &format_args!("***"))
+            .field("role", &self.role)
+            .finish()
+    }
 }
 
-async fn create_user(
-    State(pool): State<MySqlPool>, Json(payload): Json<CreateUserRequest>
-) -> Result<String, (StatusCode, String)> {
+/// 按用户名创建或更新用户, 用于从外部目录同步; 
+/// 新建时返回 `201 Created`, 更新已有用户的口令与角色时返回 `200 OK`
+// 可以指定角色, 因此要求 `users:write`
+async fn upsert_user(
+    State(users): State<Arc<dyn UserRepository>>, State(config): State<UserConfig>, 
+    _writer: RequireScope<scope::UsersWrite>, ApiJson(payload): ApiJson<UpsertUserRequest>
+) -> Result<(StatusCode, String), ApiError> {
+    if let Some(role) = &payload.role {
+        if !ROLES.contains(&role.as_str()) {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY, 
+                format!("role must be one of: {}", ROLES.join(", "))
+            ).with_field(Some("role".to_string())));
+        }
+    }
+    payload.password.check_strength().map_err(weak_password)?;
     let password_hash = payload.password.hash_with_random_salt().map_err(internal_error)?;
 
-    sqlx::query("INSERT INTO user (name, password_hash) VALUES (?,?)")
-        .bind(payload.name)
-        .bind(password_hash)
-        .execute(&pool)
+    // 目录中仍存在的用户即使已被软删除也会恢复
+    let user = UpsertUser {
+        name: payload.name.as_str(),
+        password_hash,
+        role: payload.role.as_deref(),
+    };
+    let outcome = users.upsert(user, config.max_users)
         .await
-        .map(|_| "ok".to_string())
-        .map_err(internal_error)
+        .map_err(database_error)
+        .and_then(write_outcome)?;
+
+    if outcome == WriteOutcome::Created {
+        tracing::info!(name = %payload.name, "user created by upsert");
+        metrics::counter!("users_created_total").increment(1);
+        Ok((StatusCode::CREATED, "ok".to_string()))
+    } else {
+        tracing::info!(name = %payload.name, "user updated by upsert");
+        Ok((StatusCode::OK, "ok".to_string()))
+    }
 }
 
 #[derive(Debug, Deserialize)]
-struct QueryUserParams {
-    id: Option<String>,
+#[serde(rename_all = "camelCase")]
+struct UpdateUserRequest {
+    id: UserId,
+    name: Option<Username>,
+    password: Option<UserPassword>,
+}
+
+/// 修改用户名或口令; 用户只能修改自己, 修改其他用户需要 `users:write`。
+/// 修改自己的口令需要验证旧口令, 只能通过 `/auth/change-password`
+async fn update_user(
+    State(users): State<Arc<dyn UserRepository>>, claims: Claims, ApiJson(payload): ApiJson<UpdateUserRequest>
+) -> Result<String, ApiError> {
+    let UpdateUserRequest { id, name, password } = payload;
+    if claims.id != id && !claims.has_scope::<scope::UsersWrite>() {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, AuthError::Forbidden.to_string()));
+    }
+    if claims.id == id && password.is_some() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "use /auth/change-password to change your own password")
+            .with_field(Some("password".to_string())));
+    }
+    if name.is_none() && password.is_none() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "nothing to update"));
+    }
+    let password_hash = match password {
+        Some(password) => {
+            password.check_strength().map_err(weak_password)?;
+            Some(password.hash_with_random_salt().map_err(internal_error)?)
+        }
+        None => None,
+    };
+    let changes = UserChanges {
+        name: name.as_ref().map(Username::as_str),
+        password_hash,
+    };
+    users.update(id, changes)
+        .await
+        .map_err(database_error)
+        .and_then(write_outcome)?;
+    Ok("ok".to_string())
+}
+
+/// `GET /users` 列出全部用户时每页的默认条数与上限
+const DEFAULT_PAGE_LIMIT: u32 = 20;
+const MAX_PAGE_LIMIT: u32 = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QueryUserParams {
+    #[param(value_type = Option<i32>)]
+    id: Option<UserId>,
     name: Option<String>,
+    /// 按用户名前缀搜索, 仅在未指定 id 与 name 时生效, 结果分页
+    name_prefix: Option<String>,
+    /// 只返回处于该状态的用户
+    status: Option<UserStatus>,
+    /// 只返回在此时间及之后创建的用户, RFC 3339 格式
+    #[param(value_type = Option<String>, format = DateTime)]
+    created_after: Option<chrono::DateTime<chrono::Utc>>,
+    /// 只返回在此时间及之前创建的用户, RFC 3339 格式
+    #[param(value_type = Option<String>, format = DateTime)]
+    created_before: Option<chrono::DateTime<chrono::Utc>>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "users",
+    params(QueryUserParams),
+    responses(
+        (
+            status = 200, 
+            description = "匹配的用户, 未指定 id 与 name 时分页列出全部用户或按前缀搜索的结果; 未登录时不返回邮箱与最近登录时间", 
+            body = [UserPublic],
+            headers(("x-total-count" = i64, description = "符合条件的用户总数"))
+        ),
+        (status = 400, description = "createdAfter 晚于 createdBefore", body = ErrorBody),
+        (status = 503, description = "数据库不可用, 查询遇到连接错误时已重试一次", body = ErrorBody),
+    )
+)]
+pub(crate) async fn query_user(
+    State(users): State<Arc<dyn UserRepository>>, OptionalClaims(claims): OptionalClaims, Query(params): Query<QueryUserParams>
+) -> Result<([(&'static str, String); 1], Json<Vec<UserPublic>>), ApiError> {
+    let public = |user: User| {
+        let user = UserPublic::from(user);
+        if claims.is_some() { user } else { user.redact_private() }
+    };
+    let created_after = params.created_after.map(|time| time.naive_utc());
+    let created_before = params.created_before.map(|time| time.naive_utc());
+    if let (Some(after), Some(before)) = (created_after, created_before) {
+        if after > before {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "createdAfter must not be later than createdBefore")
+                .with_field(Some("createdAfter".to_string())));
+        }
+    }
+    let status = params.status;
+    let matches_filter = |user: &User| {
+        created_after.is_none_or(|after| user.created_at >= after) 
+            && created_before.is_none_or(|before| user.created_at <= before)
+            && status.is_none_or(|status| user.status == status)
+    };
+    let found = match params {
+        QueryUserParams { id: Some(id), name, .. } => retry_read(|| users.find_by_id(id))
+            .await
+            .map_err(database_error)?
+            .filter(|user| name.as_ref().is_none_or(|name| user.name == *name)),
+        QueryUserParams { id: None, name: Some(name), .. } => retry_read(|| users.find_by_name(&name))
+            .await
+            .map_err(database_error)?,
+        QueryUserParams { id: None, name: None, name_prefix, limit, offset, .. } => {
+            let filter = UserFilter {
+                name_prefix,
+                status,
+                created_after,
+                created_before,
+                limit: limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT),
+                offset: offset.unwrap_or(0),
+            };
+            let (users, total) = retry_read(|| users.list(&filter)).await.map_err(database_error)?;
+            let users = users.into_iter().map(public).collect();
+            return Ok(([("x-total-count", total.to_string())], Json(users)));
+        }
+    };
+    let users: Vec<UserPublic> = found.filter(matches_filter).into_iter().map(public).collect();
+    Ok(([("x-total-count", users.len().to_string())], Json(users)))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct UserCount {
+    count: i64,
 }
 
-async fn query_user(
-    State(pool): State<MySqlPool>, Query(params): Query<QueryUserParams>
-) -> Result<Json<Vec<User>>, (StatusCode, String)> {
-    let query;
-    match params {
-        QueryUserParams { id: Some(id), name: Some(name) } => {
-            query = sqlx::query("SELECT id, name, password_hash, created_at FROM user WHERE id=? AND name=?")
-                .bind(id)
-                .bind(name)
-        }
-        QueryUserParams { id: Some(id), name: None } => {
-            query = sqlx::query("SELECT id, name, password_hash, created_at FROM user WHERE id=?")
-                .bind(id)
-        }
-        QueryUserParams { id: None, name: Some(name) } => {
-            query = sqlx::query("SELECT id, name, password_hash, created_at FROM user WHERE name=?")
-                .bind(name)
-        }
-        _ => return Ok(Json(vec![]))
-    }
-    let users = query.fetch_all(&pool).await.map_err(internal_error)?;
-    Ok(
-        Json(users.iter().map(|row| {
-            User {
-                id: row.get("id"),
-                name: row.get("name"),
-                password_hash: row.get("password_hash"),
-                created_at: row.get("created_at"),
+#[utoipa::path(
+    get,
+    path = "/users/count",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "未删除的用户总数", body = UserCount),
+        (status = 401, description = "令牌缺失或无效", body = ErrorBody),
+        (status = 403, description = "不是管理员", body = ErrorBody),
+    )
+)]
+pub(crate) async fn count_users(
+    State(users): State<Arc<dyn UserRepository>>, _admin: RequireRole<role::Admin>
+) -> Result<Json<UserCount>, ApiError> {
+    let count = retry_read(|| users.count()).await.map_err(database_error)?;
+    Ok(Json(UserCount { count }))
+}
+
+/// 导出时缓冲的行数, 客户端读取较慢时查询随之暂停, 内存占用与用户总数无关
+const EXPORT_BUFFER_ROWS: usize = 64;
+
+/// 以 CSV 流式导出全部未删除的用户, 只包含 id、用户名与创建时间
+async fn export_users_csv(
+    State(users): State<Arc<dyn UserRepository>>, _admin: RequireRole<role::Admin>
+) -> impl IntoResponse {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, sqlx::Error>>(EXPORT_BUFFER_ROWS);
+    // 查询产生的流借用用户存储, 因此在独立的任务中读取, 再经由通道交给响应体
+    tokio::spawn(async move {
+        if tx.send(Ok("id,name,created_at\n".to_string())).await.is_err() {
+            return;
+        }
+        let mut rows = users.export();
+        loop {
+            let line = match rows.try_next().await {
+                Ok(Some((id, name, created_at))) => Ok(format!(
+                    "{id},{},{}\n", 
+                    csv_field(&name), 
+                    created_at.and_utc().to_rfc3339()
+                )),
+                Ok(None) => break,
+                Err(err) => {
+                    tracing::error!(%err, "failed to export users");
+                    Err(err)
+                }
+            };
+            let failed = line.is_err();
+            // 客户端断开时停止查询
+            if tx.send(line).await.is_err() || failed {
+                break;
             }
-        }).collect())
+        }
+    });
+    let body = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"), 
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"users.csv\""),
+        ],
+        Body::from_stream(body),
     )
 }
 
+/// 含有逗号、引号或换行的字段用引号包裹, 内部的引号写两次
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+/// 未登录时不返回邮箱与最近登录时间
+async fn get_user(
+    State(users): State<Arc<dyn UserRepository>>, OptionalClaims(claims): OptionalClaims, Path(id): Path<UserId>
+) -> Result<Json<UserPublic>, ApiError> {
+    let user: UserPublic = retry_read(|| users.find_by_id(id))
+        .await
+        .map_err(database_error)?
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "user not found"))?
+        .into();
+    Ok(Json(if claims.is_some() { user } else { user.redact_private() }))
+}
+
+/// 按 id 读取用户, 不存在时返回 `None`
+pub async fn fetch_user(pool: &Pool, id: UserId) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>("SELECT id, name, email, password_hash, role, status, created_at, updated_at, last_login_at, failed_login_count, locked_until FROM user WHERE id=? AND deleted_at IS NULL")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// 按用户名读取用户, 不存在时返回 `None`
+pub async fn fetch_user_by_name(pool: &Pool, name: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>("SELECT id, name, email, password_hash, role, status, created_at, updated_at, last_login_at, failed_login_count, locked_until FROM user WHERE name=? AND deleted_at IS NULL")
+        .bind(name)
+        .fetch_optional(pool)
+        .await
+}
+
+async fn delete_user(
+    State(users): State<Arc<dyn UserRepository>>, _writer: RequireScope<scope::UsersWrite>, Path(id): Path<UserId>
+) -> Result<String, ApiError> {
+    // 软删除, 同时作废该用户的刷新令牌
+    users.delete(id)
+        .await
+        .map_err(database_error)
+        .and_then(write_outcome)?;
+    Ok("ok".to_string())
+}
+
+/// 恢复被软删除的用户
+async fn restore_user(
+    State(users): State<Arc<dyn UserRepository>>, State(config): State<UserConfig>, 
+    _writer: RequireScope<scope::UsersWrite>, Path(id): Path<UserId>
+) -> Result<String, ApiError> {
+    match users.restore(id, config.max_users).await.map_err(database_error)? {
+        WriteOutcome::NotFound => Err(ApiError::new(StatusCode::NOT_FOUND, "deleted user not found")),
+        outcome => write_outcome(outcome).map(|_| "ok".to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifyPasswordRequest {
+    id_or_name: Identity,
+    password: String,
+}
+
+impl std::fmt::Debug for VerifyPasswordRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VerifyPasswordRequest")
+            .field("id_or_name", &self.id_or_name)
+            .field("password", &format_args!("***"))
+            .finish()
+    }
+}
+
+// 供其他服务校验口令, 不签发令牌; 与登录一样受限流与失败锁定的约束
+async fn verify_user_password(
+    State(users): State<Arc<dyn UserRepository>>, State(config): State<AuthConfig>, client: ClientAddr, 
+    ApiJson(payload): ApiJson<VerifyPasswordRequest>
+) -> Result<String, AuthError> {
+    verify_credentials(users.as_ref(), &config, payload.id_or_name, &payload.password, client.trusted).await?;
+    Ok("ok".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        model::repository::InMemoryUsers, 
+        server::{auth::Role, rate_limit::RateLimitConfig}, 
+        testing::{json_request, lazy_pool, login, seed_user, send, test_app, test_state, test_state_with}
+    };
+
+    const PASSWORD: &str = "correct horse battery";
+
+    /// 使用内存用户存储的路由, 同时返回存储本身以便检查结果
+    fn memory_app() -> (Router, Arc<InMemoryUsers>) {
+        memory_app_with(UserConfig::default())
+    }
+
+    fn memory_app_with(user_config: UserConfig) -> (Router, Arc<InMemoryUsers>) {
+        let users = Arc::new(InMemoryUsers::default());
+        let state = AppState { user_config, ..test_state_with(lazy_pool(), users.clone()) };
+        (test_app(state), users)
+    }
+
+    fn state(users: &Arc<InMemoryUsers>) -> State<Arc<dyn UserRepository>> {
+        State(users.clone() as Arc<dyn UserRepository>)
+    }
+
+    fn admin_claims() -> Claims {
+        Claims::new(
+            UserId(1), 
+            "admin".to_string(), 
+            role::Admin::NAME.to_string(), 
+            &AuthConfig::default(), 
+            chrono::Duration::hours(1)
+        )
+    }
+
+    fn sample_user() -> User {
+        let now = chrono::Utc::now().naive_utc();
+        User {
+            id: UserId(1),
+            name: "alice".to_string(),
+            email: Some("alice@example.com".to_string()),
+            password_hash: "$2b$12$sample-password-hash".to_string(),
+            role: "user".to_string(),
+            status: UserStatus::Active,
+            created_at: now,
+            updated_at: now,
+            last_login_at: Some(now),
+            failed_login_count: 0,
+            locked_until: None,
+        }
+    }
+
+    async fn create(app: &Router, name: &str) -> StatusCode {
+        let body = json!({ "name": name, "password": PASSWORD });
+        send(app.clone(), json_request("POST", "/users", body)).await.0
+    }
+
+    #[test]
+    fn public_user_uses_camel_case_keys() {
+        let value = serde_json::to_value(UserPublic::from(sample_user())).unwrap();
+        let mut keys: Vec<_> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["createdAt", "email", "id", "lastLoginAt", "name", "role", "status", "updatedAt"]);
+    }
+
+    #[test]
+    fn public_user_never_contains_password_hash() {
+        let user = sample_user();
+        let password_hash = user.password_hash.clone();
+        for public in [UserPublic::from(user.clone()), UserPublic::from(user).redact_private()] {
+            let json = serde_json::to_string(&public).unwrap();
+            assert!(!json.contains(&password_hash), "{json}");
+            assert!(!json.to_lowercase().contains("password"), "{json}");
+        }
+    }
+
+    #[tokio::test]
+    async fn create_then_query_by_name() {
+        let (app, _) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+
+        let request = axum::http::Request::get("/users?name=alice").body(Body::empty()).unwrap();
+        let (status, headers, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-total-count"], "1");
+        assert_eq!(body[0]["name"], "alice");
+        assert!(body[0].get("passwordHash").is_none());
+    }
+
+    #[tokio::test]
+    async fn query_by_id_requires_an_integer() {
+        let (app, users) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        let id = users.find_by_name("alice").await.unwrap().unwrap().id;
+
+        let get = |uri: String| axum::http::Request::get(uri).body(Body::empty()).unwrap();
+        let (status, _, body) = send(app.clone(), get(format!("/users?id={id}"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["name"], "alice");
+        let (status, _, _) = send(app, get("/users?id=abc".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn query_filters_by_creation_time() {
+        let (app, _) = memory_app();
+        let time = || chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let start = time();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let middle = time();
+        assert_eq!(create(&app, "bob").await, StatusCode::OK);
+
+        let names = |query: String| {
+            let app = app.clone();
+            async move {
+                let request = axum::http::Request::get(format!("/users?{query}")).body(Body::empty()).unwrap();
+                let (status, _, body) = send(app, request).await;
+                assert_eq!(status, StatusCode::OK, "{body}");
+                body.as_array().unwrap().iter().map(|user| user["name"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(names(format!("createdAfter={middle}")).await, ["bob"]);
+        assert_eq!(names(format!("createdBefore={middle}")).await, ["alice"]);
+        assert_eq!(names(format!("createdAfter={start}&createdBefore={middle}")).await, ["alice"]);
+
+        let request = axum::http::Request::get(format!("/users?createdAfter={middle}&createdBefore={start}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["field"], "createdAfter");
+    }
+
+    #[tokio::test]
+    async fn create_rejects_taken_name_case_insensitively() {
+        let (app, _) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        assert_eq!(create(&app, "Alice").await, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn idempotency_key_replays_the_first_result() {
+        let (app, users) = memory_app();
+        let request = |name: &str| {
+            let mut request = json_request("POST", "/users", json!({ "name": name, "password": PASSWORD }));
+            request.headers_mut().insert("idempotency-key", "create-alice".parse().unwrap());
+            request
+        };
+
+        assert_eq!(send(app.clone(), request("alice")).await.0, StatusCode::OK);
+        // 重试不会因用户名已存在而返回 409, 也不会再创建用户
+        let (status, _, body) = send(app.clone(), request("alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "ok");
+        assert_eq!(users.count().await.unwrap(), 1);
+        // 同一个键不能用于其他请求
+        assert_eq!(send(app, request("bob")).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(users.count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn create_is_allowed_below_quota() {
+        let (app, _) = memory_app_with(UserConfig { max_users: Some(2), ..Default::default() });
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        assert_eq!(create(&app, "bob").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn create_is_rejected_at_quota() {
+        let (app, _) = memory_app_with(UserConfig { max_users: Some(1), ..Default::default() });
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        assert_eq!(create(&app, "bob").await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn batch_is_rejected_when_it_would_exceed_quota() {
+        let users = Arc::new(InMemoryUsers::default());
+        let payload = || serde_json::from_value(json!([
+            { "name": "alice", "password": PASSWORD },
+            { "name": "bob", "password": PASSWORD },
+        ])).unwrap();
+
+        let config = State(UserConfig { max_users: Some(1), ..Default::default() });
+        let err = create_users_batch(state(&users), config, RequireScope(admin_claims(), PhantomData), ApiJson(payload())).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::FORBIDDEN);
+        assert_eq!(users.count().await.unwrap(), 0);
+
+        let config = State(UserConfig { max_users: Some(2), ..Default::default() });
+        create_users_batch(state(&users), config, RequireScope(admin_claims(), PhantomData), ApiJson(payload())).await.unwrap();
+        assert_eq!(users.count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn restore_is_rejected_over_quota() {
+        let (app, users) = memory_app_with(UserConfig { max_users: Some(1), ..Default::default() });
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        let id = users.find_by_name("alice").await.unwrap().unwrap().id;
+        delete_user(state(&users), RequireScope(admin_claims(), PhantomData), Path(id)).await.unwrap();
+        assert_eq!(create(&app, "bob").await, StatusCode::OK);
+
+        let config = State(UserConfig { max_users: Some(1), ..Default::default() });
+        let err = restore_user(state(&users), config, RequireScope(admin_claims(), PhantomData), Path(id)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn verify_password_is_rate_limited() {
+        let (app, _) = memory_app();
+        // 请求体不合法, 不会访问数据库, 但同样计入限额
+        let request = || json_request("POST", "/users/verify-password", json!({}));
+        for _ in 0..RateLimitConfig::default().requests {
+            assert_eq!(send(app.clone(), request()).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        let (status, headers, _) = send(app, request()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key("retry-after"));
+    }
+
+    #[test]
+    fn request_debug_output_redacts_password() {
+        let requests: [&dyn std::fmt::Debug; 3] = [
+            &serde_json::from_value::<CreateUserRequest>(json!({ "name": "alice", "password": PASSWORD })).unwrap(),
+            &serde_json::from_value::<UpsertUserRequest>(json!({ "name": "alice", "password": PASSWORD })).unwrap(),
+            &serde_json::from_value::<VerifyPasswordRequest>(json!({ "idOrName": "alice", "password": PASSWORD })).unwrap(),
+        ];
+        for request in requests {
+            let debug = format!("{request:?}");
+            assert!(debug.contains("***"), "{debug}");
+            assert!(!debug.contains(PASSWORD), "{debug}");
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_password_records_failed_attempts() {
+        let (app, users) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+
+        let request = |password: &str| json_request(
+            "POST", "/users/verify-password", json!({ "idOrName": "alice", "password": password })
+        );
+        assert_eq!(send(app.clone(), request("wrong password")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(users.find_by_name("alice").await.unwrap().unwrap().failed_login_count, 1);
+
+        assert_eq!(send(app, request(PASSWORD)).await.0, StatusCode::OK);
+        assert_eq!(users.find_by_name("alice").await.unwrap().unwrap().failed_login_count, 0);
+    }
+
+    #[tokio::test]
+    async fn batch_skips_conflicts_and_weak_passwords() {
+        let (app, users) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+
+        let payload = serde_json::from_value(json!([
+            { "name": "alice", "password": PASSWORD },
+            { "name": "bob", "password": PASSWORD },
+            { "name": "carol", "password": "short" },
+        ])).unwrap();
+        let Json(results) = create_users_batch(
+            state(&users), State(UserConfig::default()), RequireScope(admin_claims(), PhantomData), ApiJson(payload)
+        ).await.unwrap();
+        let statuses: Vec<_> = results.iter().map(|result| &result.status).collect();
+        assert!(matches!(statuses[0], BatchCreateStatus::Conflict { .. }));
+        assert!(matches!(statuses[1], BatchCreateStatus::Created));
+        assert!(matches!(statuses[2], BatchCreateStatus::Invalid { .. }));
+        assert_eq!(users.count().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn batch_requires_write_scope() {
+        let (app, users) = memory_app();
+        let body = json!([{ "name": "alice", "password": PASSWORD }]);
+        let (status, _, _) = send(app, json_request("POST", "/users/batch", body)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(users.count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn upsert_creates_then_updates() {
+        let (_, users) = memory_app();
+        let upsert = |role: Option<&str>| {
+            let payload = serde_json::from_value(json!({ "name": "alice", "password": PASSWORD, "role": role })).unwrap();
+            upsert_user(state(&users), State(UserConfig::default()), RequireScope(admin_claims(), PhantomData), ApiJson(payload))
+        };
+        assert_eq!(upsert(None).await.unwrap().0, StatusCode::CREATED);
+        assert_eq!(upsert(Some("admin")).await.unwrap().0, StatusCode::OK);
+        assert_eq!(users.find_by_name("alice").await.unwrap().unwrap().role, "admin");
+        assert_eq!(upsert(Some("root")).await.unwrap_err().status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn delete_and_restore() {
+        let (app, users) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+        let id = users.find_by_name("alice").await.unwrap().unwrap().id;
+
+        delete_user(state(&users), RequireScope(admin_claims(), PhantomData), Path(id)).await.unwrap();
+        assert!(users.find_by_id(id).await.unwrap().is_none());
+        assert_eq!(delete_user(state(&users), RequireScope(admin_claims(), PhantomData), Path(id)).await.unwrap_err().status, StatusCode::NOT_FOUND);
+        // 软删除的用户仍占用用户名
+        assert_eq!(create(&app, "alice").await, StatusCode::CONFLICT);
+
+        restore_user(state(&users), State(UserConfig::default()), RequireScope(admin_claims(), PhantomData), Path(id)).await.unwrap();
+        assert!(users.find_by_id(id).await.unwrap().is_some());
+        assert_eq!(restore_user(state(&users), State(UserConfig::default()), RequireScope(admin_claims(), PhantomData), Path(id)).await.unwrap_err().status, StatusCode::NOT_FOUND);
+    }
+
+    fn user_claims(id: UserId) -> Claims {
+        Claims::new(id, "alice".to_string(), "user".to_string(), &AuthConfig::default(), chrono::Duration::hours(1))
+    }
+
+    #[tokio::test]
+    async fn update_requires_same_user_or_write_scope() {
+        let (app, users) = memory_app();
+        for name in ["alice", "bob"] {
+            assert_eq!(create(&app, name).await, StatusCode::OK);
+        }
+        let alice = users.find_by_name("alice").await.unwrap().unwrap().id;
+        let bob = users.find_by_name("bob").await.unwrap().unwrap().id;
+        let rename = |claims: Claims, id: UserId, name: &str| {
+            let payload = serde_json::from_value(json!({ "id": id, "name": name })).unwrap();
+            update_user(state(&users), claims, ApiJson(payload))
+        };
+
+        rename(user_claims(alice), alice, "alice2").await.unwrap();
+        assert_eq!(rename(user_claims(alice), bob, "mallory").await.unwrap_err().status, StatusCode::FORBIDDEN);
+        assert_eq!(users.find_by_id(bob).await.unwrap().unwrap().name, "bob");
+        rename(admin_claims(), bob, "bob2").await.unwrap();
+        assert_eq!(users.find_by_id(bob).await.unwrap().unwrap().name, "bob2");
+    }
+
+    #[tokio::test]
+    async fn update_rejects_own_password() {
+        let (app, users) = memory_app();
+        for name in ["alice", "bob"] {
+            assert_eq!(create(&app, name).await, StatusCode::OK);
+        }
+        let alice = users.find_by_name("alice").await.unwrap().unwrap();
+        let bob = users.find_by_name("bob").await.unwrap().unwrap();
+        let set_password = |claims: Claims, id: UserId| {
+            let payload = serde_json::from_value(json!({ "id": id, "password": "Another-Strong-Pass-2" })).unwrap();
+            update_user(state(&users), claims, ApiJson(payload))
+        };
+
+        let err = set_password(user_claims(alice.id), alice.id).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert_eq!(err.field.as_deref(), Some("password"));
+        assert_eq!(users.find_by_id(alice.id).await.unwrap().unwrap().password_hash, alice.password_hash);
+        // 管理员可以重置其他用户的口令
+        set_password(admin_claims(), bob.id).await.unwrap();
+        assert_ne!(users.find_by_id(bob.id).await.unwrap().unwrap().password_hash, bob.password_hash);
+    }
+
+    #[tokio::test]
+    async fn update_to_taken_name_conflicts() {
+        let (app, users) = memory_app();
+        for name in ["alice", "bob"] {
+            assert_eq!(create(&app, name).await, StatusCode::OK);
+        }
+        let alice = users.find_by_name("alice").await.unwrap().unwrap().id;
+        let payload = serde_json::from_value(json!({ "id": alice, "name": "bob" })).unwrap();
+        let err = update_user(state(&users), user_claims(alice), ApiJson(payload)).await.unwrap_err();
+        assert_eq!(err.status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn delete_requires_write_scope() {
+        let (app, _) = memory_app();
+        assert_eq!(create(&app, "alice").await, StatusCode::OK);
+
+        let request = axum::http::Request::delete("/users/1").body(Body::empty()).unwrap();
+        let (status, _, _) = send(app, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn restore_requires_write_scope() {
+        let (app, _) = memory_app();
+        let request = axum::http::Request::post("/users/1/restore").body(Body::empty()).unwrap();
+        let (status, headers, _) = send(app, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(headers.contains_key("www-authenticate"));
+    }
+
+    #[tokio::test]
+    async fn count_excludes_deleted_users() {
+        let (app, users) = memory_app();
+        for name in ["alice", "bob"] {
+            assert_eq!(create(&app, name).await, StatusCode::OK);
+        }
+        let id = users.find_by_name("bob").await.unwrap().unwrap().id;
+        users.delete(id).await.unwrap();
+
+        let Json(count) = count_users(state(&users), RequireRole(admin_claims(), PhantomData)).await.unwrap();
+        assert_eq!(count.count, 1);
+    }
+
+    /// 创建带有邮箱的用户 alice, 返回其 id
+    async fn create_with_email(app: &Router, users: &InMemoryUsers) -> UserId {
+        let body = json!({ "name": "alice", "email": "alice@example.com", "password": PASSWORD });
+        assert_eq!(send(app.clone(), json_request("POST", "/users", body)).await.0, StatusCode::OK);
+        users.find_by_name("alice").await.unwrap().unwrap().id
+    }
+
+    #[tokio::test]
+    async fn get_user_hides_private_fields_without_token() {
+        let (app, users) = memory_app();
+        let id = create_with_email(&app, &users).await;
+
+        let request = axum::http::Request::get(format!("/users/{id}")).body(Body::empty()).unwrap();
+        let (status, _, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["name"], "alice");
+        assert!(body["email"].is_null());
+    }
+
+    #[tokio::test]
+    async fn query_user_hides_private_fields_without_token() {
+        let (app, users) = memory_app();
+        create_with_email(&app, &users).await;
+
+        for uri in ["/users", "/users?name=alice"] {
+            let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+            let (status, _, body) = send(app.clone(), request).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            assert_eq!(body[0]["name"], "alice", "{uri}");
+            assert!(body[0]["email"].is_null(), "{uri}");
+            assert!(body[0]["lastLoginAt"].is_null(), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn get_user_rejects_malformed_token() {
+        let (app, users) = memory_app();
+        let id = create_with_email(&app, &users).await;
+
+        for authorization in ["Bearer not-a-token", "Basic YWxpY2U6c2VjcmV0"] {
+            let request = axum::http::Request::get(format!("/users/{id}"))
+                .header("authorization", authorization)
+                .body(Body::empty())
+                .unwrap();
+            let (status, _, body) = send(app.clone(), request).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{authorization}");
+            assert_eq!(body["error"], "Invalid token");
+        }
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn get_user_shows_private_fields_with_valid_token(pool: Pool) {
+        let id = seed_user(&pool, "alice", PASSWORD, "user").await;
+        sqlx::query("UPDATE user SET email='alice@example.com' WHERE id=?")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let app = test_app(test_state(pool));
+        let token = login(app.clone(), "alice", PASSWORD).await;
+
+        let request = axum::http::Request::get(format!("/users/{id}"))
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["email"], "alice@example.com");
+        assert!(!body["lastLoginAt"].is_null());
+    }
+
+    #[test]
+    fn username_accepts_valid_names() {
+        for name in ["abc", "alice_01", "a.b-c", "用户名", &"a".repeat(USERNAME_MAX_LEN)] {
+            assert!(Username::try_from(name.to_string()).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn username_rejects_invalid_names() {
+        assert!(matches!(Username::try_from("ab".to_string()), Err(InvalidUsername::TooShort { .. })));
+        assert!(matches!(
+            Username::try_from("a".repeat(USERNAME_MAX_LEN + 1)), 
+            Err(InvalidUsername::TooLong { .. })
+        ));
+        assert!(matches!(Username::try_from("al ice".to_string()), Err(InvalidUsername::InvalidCharacter(' '))));
+        assert!(matches!(Username::try_from("alice@x".to_string()), Err(InvalidUsername::InvalidCharacter('@'))));
+    }
+
+    #[test]
+    fn username_deserializes_through_validation() {
+        assert!(serde_json::from_str::<Username>("\"alice\"").is_ok());
+        assert!(serde_json::from_str::<Username>("\"a\"").is_err());
+    }
+
+    #[test]
+    fn email_accepts_well_formed_addresses() {
+        for email in ["a@example.com", "first.last+tag@mail.example.org"] {
+            assert!(Email::try_from(email.to_string()).is_ok(), "{email}");
+        }
+    }
 
+    #[test]
+    fn email_rejects_malformed_addresses() {
+        for email in ["", "example.com", "@example.com", "a@", "a@b", "a@@b.com", "a@b..com", "a b@example.com", "a@example.com."] {
+            assert!(matches!(Email::try_from(email.to_string()), Err(InvalidEmail::Malformed)), "{email}");
+        }
+        let long = format!("{}@example.com", "a".repeat(EMAIL_MAX_LEN));
+        assert!(matches!(Email::try_from(long), Err(InvalidEmail::TooLong { .. })));
+    }
+
+    #[test]
+    fn csv_field_quotes_only_when_needed() {
+        assert_eq!(csv_field("alice"), "alice");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert!(matches!(csv_field("alice"), std::borrow::Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn user_status_round_trips_through_str() {
+        for status in [UserStatus::Active, UserStatus::Suspended, UserStatus::Pending] {
+            assert_eq!(status.as_str().parse::<UserStatus>(), Ok(status));
+        }
+        assert!("Active".parse::<UserStatus>().is_err());
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn user_status_decodes_from_varchar_column(pool: Pool) {
+        let id = seed_user(&pool, "alice", "correct horse battery", "user").await;
+        sqlx::query("UPDATE user SET status=? WHERE id=?")
+            .bind(UserStatus::Suspended)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let user = fetch_user(&pool, id).await.unwrap().expect("user exists");
+        assert_eq!(user.status, UserStatus::Suspended);
+    }
+}
@@ -16,78 +16,276 @@
 *   along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::fmt::Display;
+use std::{fmt::Display, marker::PhantomData, sync::{Arc, LazyLock}};
 
-use axum::{extract::{FromRequestParts, State}, http::StatusCode, response::IntoResponse, routing::{get, post}, Json, RequestPartsExt, Router};
-use axum_extra::{headers::{authorization::Bearer, Authorization}, TypedHeader};
+use axum::{extract::{FromRef, FromRequestParts, State}, http::{header, HeaderMap, HeaderName, StatusCode}, middleware, response::{AppendHeaders, IntoResponse}, routing::{get, post}, Json, RequestPartsExt, Router};
+use axum_extra::{headers::{authorization::Bearer, Authorization, Cookie, HeaderMapExt}, TypedHeader};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::Row;
+use utoipa::ToSchema;
 
-use crate::util::keys::AuthKeys;
+use crate::{
+    database::Pool,
+    model::{repository::UserRepository, user::{fetch_user, weak_password, User, UserId, UserPassword, UserPasswordProperties, UserPublic, UserStatus}}, 
+    server::{client_ip::ClientAddr, rate_limit::{rate_limit, RateLimiter}, state::AppState}, 
+    util::{error::{database_error, internal_error, ApiError, ErrorBody}, json::ApiJson, keys::{AuthKeys, Keys}, password::{needs_rehash, verify_password, StringPassword}}
+};
 
-pub fn auth_router<D>() -> Router<(sqlx::MySqlPool, D)> 
-where 
-    D: AuthKeys + Clone + Send + Sync + 'static 
-{
+pub fn auth_router(limiter: Arc<RateLimiter>) -> Router<AppState> {
     Router::new()
-        .route("/authorize", post(authorize))
+        .route(
+            "/authorize", 
+            post(authorize).layer(middleware::from_fn_with_state(limiter, rate_limit))
+        )
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
+        .route("/change-password", post(change_password))
+        .route("/me", get(me))
+        .route("/token-info", get(token_info))
         .route("/protected", get(protected))
+        .route("/admin", get(admin))
 }
 
-#[derive(Debug, Deserialize)]
-struct AuthPayload {
-    id: Option<i32>,
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// 访问令牌的有效期(秒)
+    pub token_ttl: i64,
+    /// 刷新令牌的有效期(秒)
+    pub refresh_token_ttl: i64,
+    /// 签发与校验令牌时使用的 `iss`
+    pub issuer: String,
+    /// 签发与校验令牌时使用的 `aud`
+    pub audience: String,
+    /// 校验 `exp` 与 `iat` 时容许的时钟偏差(秒)
+    pub leeway: u64,
+    /// 连续登录失败多少次后锁定账户
+    pub lockout_threshold: i32,
+    /// 账户锁定的时长(秒)
+    pub lockout_cooldown: i64,
+    /// 是否同时通过 httpOnly cookie 下发与接收访问令牌, 见 [`ACCESS_TOKEN_COOKIE`]
+    pub cookie_auth: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { 
+            token_ttl: 3600,
+            refresh_token_ttl: 30 * 24 * 3600,
+            issuer: "auto-planning-backend".to_string(),
+            audience: "auto-planning-backend".to_string(),
+            leeway: 30,
+            lockout_threshold: 5,
+            lockout_cooldown: 15 * 60,
+            cookie_auth: false,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AuthPayload {
+    #[schema(value_type = Option<i32>)]
+    id: Option<UserId>,
     name: Option<String>,
+    email: Option<String>,
     password: String,
 }
 
-#[derive(Debug, Serialize)]
-struct AuthBody {
+// 口令不应出现在日志中
+impl std::fmt::Debug for AuthPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthPayload")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("email", &self.email)
+            .field("password", &format_args!("***"))
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RefreshPayload {
+    refresh_token: String,
+}
+
+impl std::fmt::Debug for RefreshPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RefreshPayload")
+            .field("refresh_token", &format_args!("***"))
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AuthBody {
     access_token: String,
+    refresh_token: String,
     token_type: String,
 }
 
 impl AuthBody {
-    fn new(access_token: String) -> Self {
+    fn new(access_token: String, refresh_token: String) -> Self {
         Self {
             access_token,
+            refresh_token,
             token_type: "Bearer".to_string(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, ToSchema)]
 pub enum AuthError {
     WrongCredentials,
     MissingCredentials,
     TokenCreation,
     InvalidToken,
-    MissingToken
+    MissingToken,
+    InvalidRefreshToken,
+    Forbidden,
+    AccountLocked,
+    /// 账户被停用或尚未启用
+    AccountInactive,
+    /// 通过 cookie 认证的写请求缺少或带错了 CSRF 令牌, 见 [`CSRF_HEADER`]
+    CsrfMismatch,
+    /// 数据库连接失败, 与令牌是否有效无关, 客户端应稍后重试而不是丢弃令牌
+    Unavailable,
+    Internal,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            AuthError::WrongCredentials => "Wrong credentials",
+            AuthError::MissingCredentials => "Missing credentials: provide a password and exactly one of id, name or email",
+            AuthError::TokenCreation => "Token creation error",
+            AuthError::InvalidToken => "Invalid token",
+            AuthError::MissingToken => "Missing token",
+            AuthError::InvalidRefreshToken => "Invalid or expired refresh token",
+            AuthError::Forbidden => "Insufficient permissions",
+            AuthError::AccountLocked => "Account temporarily locked after repeated failed logins",
+            AuthError::AccountInactive => "Account is not active",
+            AuthError::CsrfMismatch => "CSRF token missing or mismatched",
+            AuthError::Unavailable => "Database unavailable, try again later",
+            AuthError::Internal => "Internal server error",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+// 令牌只在提取 `Claims` 时解码, 解码失败说明令牌无效
+impl From<jsonwebtoken::errors::Error> for AuthError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        tracing::warn!(%err, "token rejected");
+        AuthError::InvalidToken
+    }
 }
 
+// 与 `database_error` 的划分一致: 连接错误为 503, 其余为 500; 原因只记录在日志中, 不返回给客户端
+impl From<sqlx::Error> for AuthError {
+    fn from(err: sqlx::Error) -> Self {
+        match database_error(err).status {
+            StatusCode::SERVICE_UNAVAILABLE => AuthError::Unavailable,
+            _ => AuthError::Internal,
+        }
+    }
+}
+
+/// `WWW-Authenticate` 中的 realm
+const WWW_AUTHENTICATE_REALM: &str = "auto-planning-backend";
+
 impl IntoResponse for AuthError {
     fn into_response(self) -> axum::response::Response {
-        let (status, error_message) = match self {
-            AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-            AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
-            AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
-            AuthError::MissingToken => (StatusCode::BAD_REQUEST, "Missing token")
+        // `reason` 用于指标; `challenge` 为 RFC 6750 定义的错误码, 只有访问令牌本身的问题才会给出
+        let (status, reason, challenge) = match self {
+            AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "wrong_credentials", None),
+            AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "missing_credentials", None),
+            AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "token_creation", None),
+            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "invalid_token", Some("invalid_token")),
+            // RFC 6750 3.1: 请求未携带认证信息时, 响应不应包含错误码
+            AuthError::MissingToken => (StatusCode::UNAUTHORIZED, "missing_token", None),
+            AuthError::InvalidRefreshToken => (StatusCode::UNAUTHORIZED, "invalid_refresh_token", None),
+            AuthError::Forbidden => (StatusCode::FORBIDDEN, "forbidden", Some("insufficient_scope")),
+            AuthError::AccountLocked => (StatusCode::LOCKED, "account_locked", None),
+            AuthError::AccountInactive => (StatusCode::FORBIDDEN, "account_inactive", None),
+            AuthError::CsrfMismatch => (StatusCode::FORBIDDEN, "csrf_mismatch", None),
+            AuthError::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, "unavailable", None),
+            AuthError::Internal => (StatusCode::INTERNAL_SERVER_ERROR, "internal", None),
         };
+        // 用于发现暴力破解等异常
+        metrics::counter!("auth_failures_total", "reason" => reason).increment(1);
         let body = Json(json!({
-            "error": error_message
+            "error": self.to_string(),
+            "code": status.as_u16(),
         }));
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        // RFC 7235: 401 响应必须说明认证方式; RFC 6750: 权限不足的 403 同样带上错误码
+        let challenge = match challenge {
+            Some(error) => format!("Bearer realm=\"{WWW_AUTHENTICATE_REALM}\", error=\"{error}\""),
+            None if status == StatusCode::UNAUTHORIZED => format!("Bearer realm=\"{WWW_AUTHENTICATE_REALM}\""),
+            None => return response,
+        };
+        if let Ok(value) = axum::http::HeaderValue::from_str(&challenge) {
+            response.headers_mut().insert(axum::http::header::WWW_AUTHENTICATE, value);
+        }
+        response
     }
 }
 
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-    pub id: i32,
+    pub id: UserId,
     pub name: String,
+    pub role: String,
+    pub iss: String,
+    pub aud: String,
     pub exp: i64,
+    /// 签发时间
+    pub iat: i64,
+    /// 权限范围, 由角色决定, 见 [`scopes_for_role`]
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// 令牌的唯一标识, 用于注销
+    pub jti: String,
+}
+
+impl Claims {
+    /// 以当前时间为签发时间, 按 `ttl` 计算 `exp`, 并生成新的 `jti`
+    pub fn new(id: UserId, name: String, role: String, config: &AuthConfig, ttl: chrono::Duration) -> Self {
+        let iat = chrono::Utc::now().timestamp();
+        Self {
+            id,
+            name,
+            scopes: scopes_for_role(&role),
+            role,
+            iss: config.issuer.clone(),
+            aud: config.audience.clone(),
+            exp: iat + ttl.num_seconds(),
+            iat,
+            jti: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// 在容许 `leeway` 秒时钟偏差的前提下是否已过期; 与 RFC 7519 一致, 到达 `exp` 时即已过期
+    pub fn is_expired(&self, leeway: u64) -> bool {
+        self.exp.saturating_add_unsigned(leeway) <= chrono::Utc::now().timestamp()
+    }
+
+    /// 签发时间晚于当前时间(超出 `leeway`)的令牌不可能由本服务签发
+    pub fn is_issued_in_future(&self, leeway: u64) -> bool {
+        self.iat > chrono::Utc::now().timestamp().saturating_add_unsigned(leeway)
+    }
+
+    /// 令牌是否带有权限范围 `P`
+    pub fn has_scope<P: Scope>(&self) -> bool {
+        self.scopes.iter().any(|scope| scope == P::NAME)
+    }
 }
 
 impl Display for Claims {
@@ -101,78 +299,1076 @@ impl Display for Claims {
             )
             .to_string();
 
-        write!(f, "ID: {}\nName: {}\nExpire: {}", self.id, self.name, expire)
+        write!(f, "ID: {}\nName: {}\nRole: {}\nExpire: {}", self.id, self.name, self.role, expire)
     }
 }
 
-impl<T> FromRequestParts<(sqlx::MySqlPool, T)> for Claims
-where T: AuthKeys + Send + Sync {
+/// 角色标记, `NAME` 与数据库 `user.role` 列中的取值对应
+pub trait Role {
+    const NAME: &'static str;
+}
+
+pub mod role {
+    #[derive(Debug)]
+    pub struct Admin;
+
+    impl super::Role for Admin {
+        const NAME: &'static str = "admin";
+    }
+}
+
+/// 权限范围标记, `NAME` 与令牌 `scopes` 中的取值对应
+pub trait Scope {
+    const NAME: &'static str;
+}
+
+pub mod scope {
+    #[derive(Debug)]
+    pub struct UsersRead;
+
+    impl super::Scope for UsersRead {
+        const NAME: &'static str = "users:read";
+    }
+
+    #[derive(Debug)]
+    pub struct UsersWrite;
+
+    impl super::Scope for UsersWrite {
+        const NAME: &'static str = "users:write";
+    }
+}
+
+/// 角色拥有的权限范围, 签发令牌时写入 `scopes`; 角色变更后需重新登录才会生效
+pub fn scopes_for_role(role: &str) -> Vec<String> {
+    let scopes: &[&str] = if role == role::Admin::NAME {
+        &[scope::UsersRead::NAME, scope::UsersWrite::NAME]
+    } else {
+        &[scope::UsersRead::NAME]
+    };
+    scopes.iter().map(|scope| scope.to_string()).collect()
+}
+
+/// 要求令牌带有角色 `R` 的提取器, 否则拒绝为 `403 Forbidden`
+#[derive(Debug)]
+pub struct RequireRole<R: Role>(pub Claims, pub PhantomData<R>);
+
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where 
+    S: Send + Sync, 
+    R: Role, 
+    Pool: FromRef<S>, 
+    Arc<Keys>: FromRef<S>, 
+    AuthConfig: FromRef<S> 
+{
     type Rejection = AuthError;
 
-    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &(sqlx::MySqlPool, T)) -> Result<Self, Self::Rejection>  {
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|_| AuthError::InvalidToken)?;
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &S) -> Result<Self, Self::Rejection>  {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        if claims.role != R::NAME {
+            return Err(AuthError::Forbidden);
+        }
+        Ok(Self(claims, PhantomData))
+    }
+}
 
-        let validation = &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
-        
+/// 要求令牌带有权限范围 `P` 的提取器, 否则拒绝为 `403 Forbidden`; 可与 [`RequireRole`] 同时使用
+#[derive(Debug)]
+pub struct RequireScope<P: Scope>(pub Claims, pub PhantomData<P>);
+
+impl<S, P> FromRequestParts<S> for RequireScope<P>
+where 
+    S: Send + Sync, 
+    P: Scope, 
+    Pool: FromRef<S>, 
+    Arc<Keys>: FromRef<S>, 
+    AuthConfig: FromRef<S> 
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &S) -> Result<Self, Self::Rejection>  {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        if !claims.has_scope::<P>() {
+            return Err(AuthError::Forbidden);
+        }
+        Ok(Self(claims, PhantomData))
+    }
+}
+
+impl<S> FromRequestParts<S> for Claims
+where 
+    S: Send + Sync, 
+    Pool: FromRef<S>, 
+    Arc<Keys>: FromRef<S>, 
+    AuthConfig: FromRef<S> 
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &S) -> Result<Self, Self::Rejection>  {
+        let keys = Arc::<Keys>::from_ref(state);
+        let config = AuthConfig::from_ref(state);
+
+        // `Authorization` 头优先; 没有时, 启用 cookie 认证则从 cookie 读取令牌
+        let token = match parts.extract::<TypedHeader<Authorization<Bearer>>>().await {
+            Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+            Err(rejection) if rejection.is_missing() && config.cookie_auth => token_from_cookie(parts)?,
+            Err(rejection) if rejection.is_missing() => return Err(AuthError::MissingToken),
+            Err(_) => return Err(AuthError::InvalidToken),
+        };
+
+        let mut validation = jsonwebtoken::Validation::new(keys.get_algorithm());
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation.set_issuer(&[&config.issuer]);
+        validation.set_audience(&[&config.audience]);
+        // 过期与签发时间由 `Claims` 自行校验, 这里只检查 `exp` 是否存在
+        validation.validate_exp = false;
+
+        // 按 `kid` 选择密钥, 轮换前签发的令牌仍可验证
+        let header = jsonwebtoken::decode_header(&token)?;
+        let decoding = keys.get_decoding_by_kid(header.kid.as_deref()).ok_or(AuthError::InvalidToken)?;
         let token_date = jsonwebtoken::decode::<Claims>(
-            bearer.token(), state.1.get_decoding(), &validation 
-        ).map_err(|_| AuthError::InvalidToken)?;
-        if token_date.claims.exp <= chrono::Utc::now().timestamp() {
+            &token, decoding, &validation 
+        )?;
+        // 容忍客户端与服务端之间的时钟偏差
+        if token_date.claims.is_expired(config.leeway) {
+            return Err(AuthError::InvalidToken);
+        }
+        if token_date.claims.is_issued_in_future(config.leeway) {
+            tracing::warn!(iat = token_date.claims.iat, "token issued in the future");
+            return Err(AuthError::InvalidToken);
+        }
+
+        // 以下查询失败时返回 500 或 503 而不是 401, 以免数据库故障时客户端丢弃仍然有效的令牌
+        let revoked = sqlx::query("SELECT 1 FROM revoked_token WHERE jti=?")
+            .bind(&token_date.claims.jti)
+            .fetch_optional(&Pool::from_ref(state))
+            .await?;
+        if revoked.is_some() {
+            return Err(AuthError::InvalidToken);
+        }
+
+        // 口令修改前签发的令牌作废; `iat` 只精确到秒, 因此按秒比较
+        let valid_after: Option<Option<chrono::NaiveDateTime>> = sqlx::query_scalar(
+                "SELECT tokens_valid_after FROM user WHERE id=?"
+            )
+            .bind(token_date.claims.id)
+            .fetch_optional(&Pool::from_ref(state))
+            .await?;
+        if valid_after.flatten().is_some_and(|valid_after| token_date.claims.iat < valid_after.and_utc().timestamp()) {
             return Err(AuthError::InvalidToken);
         }
         Ok(token_date.claims)
     }
 }
 
-async fn authorize(State((pool, keys)): State<(sqlx::MySqlPool, impl AuthKeys)>, Json(payload): Json<AuthPayload>) -> Result<Json<AuthBody>, AuthError> {
+/// 启用 cookie 认证时保存访问令牌的 cookie, 带有 `HttpOnly`, 脚本无法读取
+pub const ACCESS_TOKEN_COOKIE: &str = "apb_access_token";
+/// 双重提交 CSRF 令牌的 cookie, 前端读取后放入 [`CSRF_HEADER`] 请求头
+pub const CSRF_COOKIE: &str = "apb_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
 
-    let query = match payload {
-        AuthPayload { password: password_hash, .. } if password_hash.is_empty() => {
-            return Err(AuthError::MissingCredentials);
+fn cookie_value(parts: &axum::http::request::Parts, name: &str) -> Option<String> {
+    parts.headers
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(name).map(str::to_string))
+}
+
+/// 从 cookie 读取访问令牌; 会修改状态的请求还须携带与 CSRF cookie 相同的 [`CSRF_HEADER`], 
+/// 第三方站点能让浏览器带上 cookie, 但读不到 cookie 的值, 因此无法伪造该请求头
+fn token_from_cookie(parts: &axum::http::request::Parts) -> Result<String, AuthError> {
+    let token = cookie_value(parts, ACCESS_TOKEN_COOKIE).ok_or(AuthError::MissingToken)?;
+    if !parts.method.is_safe() {
+        let cookie = cookie_value(parts, CSRF_COOKIE);
+        let header = parts.headers.get(CSRF_HEADER).and_then(|value| value.to_str().ok());
+        match (cookie, header) {
+            (Some(cookie), Some(header)) if constant_time_eq(cookie.as_bytes(), header.as_bytes()) => {}
+            _ => {
+                tracing::warn!(method = %parts.method, "CSRF token missing or mismatched");
+                return Err(AuthError::CsrfMismatch);
+            }
         }
-        AuthPayload { id: Some(id), .. } => {
-            sqlx::query("SELECT id, name, password_hash FROM user WHERE id=?")
-                .bind(id)
+    }
+    Ok(token)
+}
+
+/// 比较耗时只与长度有关, 不泄露相同前缀的长度
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// 启用 cookie 认证时, 随令牌下发的 `Set-Cookie`; 未启用时为空
+fn token_cookies(config: &AuthConfig, body: &AuthBody) -> Result<AppendHeaders<Vec<(HeaderName, String)>>, AuthError> {
+    if !config.cookie_auth {
+        return Ok(AppendHeaders(Vec::new()));
+    }
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|err| {
+        tracing::error!(%err, "failed to generate CSRF token");
+        AuthError::TokenCreation
+    })?;
+    let max_age = config.token_ttl;
+    Ok(AppendHeaders(vec![
+        (
+            header::SET_COOKIE, 
+            format!("{ACCESS_TOKEN_COOKIE}={}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Strict", body.access_token)
+        ),
+        (
+            header::SET_COOKIE, 
+            format!("{CSRF_COOKIE}={}; Path=/; Max-Age={max_age}; Secure; SameSite=Strict", to_hex(&bytes))
+        ),
+    ]))
+}
+
+/// 注销时清除令牌 cookie
+fn clear_token_cookies(config: &AuthConfig) -> AppendHeaders<Vec<(HeaderName, String)>> {
+    if !config.cookie_auth {
+        return AppendHeaders(Vec::new());
+    }
+    AppendHeaders(
+        [ACCESS_TOKEN_COOKIE, CSRF_COOKIE]
+            .into_iter()
+            .map(|name| (header::SET_COOKIE, format!("{name}=; Path=/; Max-Age=0; Secure; SameSite=Strict")))
+            .collect()
+    )
+}
+
+/// 可选的身份认证: 没有 `Authorization` 头时为 `None`, 
+/// 带有令牌但令牌无效时仍然拒绝, 以免客户端误以为自己已登录
+#[derive(Debug)]
+pub struct OptionalClaims(pub Option<Claims>);
+
+impl<S> FromRequestParts<S> for OptionalClaims
+where 
+    S: Send + Sync, 
+    Pool: FromRef<S>, 
+    Arc<Keys>: FromRef<S>, 
+    AuthConfig: FromRef<S> 
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &S) -> Result<Self, Self::Rejection>  {
+        let config = AuthConfig::from_ref(state);
+        let has_cookie = config.cookie_auth && cookie_value(parts, ACCESS_TOKEN_COOKIE).is_some();
+        if !parts.headers.contains_key(axum::http::header::AUTHORIZATION) && !has_cookie {
+            return Ok(Self(None));
         }
-        AuthPayload { name: Some(name), .. } => {
-            sqlx::query("SELECT id, name, password_hash FROM user WHERE name=?")
-                .bind(name)
+        Claims::from_request_parts(parts, state).await.map(|claims| Self(Some(claims)))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/authorize",
+    tag = "auth",
+    request_body = AuthPayload,
+    responses(
+        (status = 200, description = "登录成功", body = AuthBody),
+        (status = 400, description = "缺少口令, 或未给出恰好一个身份(id、name、email)", body = ErrorBody),
+        (status = 401, description = "凭据错误", body = ErrorBody),
+        (status = 415, description = "请求体不是 JSON", body = ErrorBody),
+        (status = 403, description = "账户已停用或尚未启用", body = ErrorBody),
+        (status = 423, description = "连续登录失败, 账户暂时锁定", body = ErrorBody),
+        (status = 429, description = "请求过于频繁", body = ErrorBody),
+    )
+)]
+pub(crate) async fn authorize(
+    State(pool): State<Pool>, State(users): State<Arc<dyn UserRepository>>, State(keys): State<Arc<Keys>>, State(config): State<AuthConfig>, 
+    client: ClientAddr, ApiJson(payload): ApiJson<AuthPayload>
+) -> Result<(AppendHeaders<Vec<(HeaderName, String)>>, Json<AuthBody>), AuthError> {
+
+    // 同时给出多个身份时不猜测客户端的意图, 与未给出身份一样拒绝
+    let identity = match payload {
+        AuthPayload { password: password_hash, .. } if password_hash.is_empty() => {
+            return Err(AuthError::MissingCredentials);
         }
+        AuthPayload { id: Some(id), name: None, email: None, .. } => Identity::Id(id),
+        AuthPayload { id: None, name: Some(name), email: None, .. } => Identity::Name(name),
+        AuthPayload { id: None, name: None, email: Some(email), .. } => Identity::Email(email),
         _ => { 
-            return Err(AuthError::MissingToken);
+            return Err(AuthError::MissingCredentials);
         }
     };
 
-    let row = query.fetch_one(&pool)
-        .await
-        .map_err(|_| AuthError::InvalidToken)?;
+    let user = verify_credentials(users.as_ref(), &config, identity, &payload.password, client.trusted).await?;
+    if let Err(err) = users.record_login(user.id, chrono::Utc::now().naive_utc()).await {
+        tracing::warn!(id = %user.id, %err, "failed to record last login time");
+    }
+    tracing::info!(id = %user.id, "user authorized");
+    metrics::counter!("auth_successes_total").increment(1);
+    let body = issue_tokens(&pool, &keys, &config, user.id, user.name, user.role).await?;
+    Ok((token_cookies(&config, &body)?, Json(body)))
+}
+
+/// 登录身份, 按 id、用户名或邮箱查找用户
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Identity {
+    Id(UserId),
+    Name(String),
+    // 与 `Name` 同为字符串, 无法由 untagged 区分, 只能显式构造
+    #[serde(skip_deserializing)]
+    Email(String),
+}
+
+/// 按身份查找用户
+pub async fn find_user_by_identity(users: &dyn UserRepository, identity: Identity) -> Result<User, AuthError> {
+    let user = match identity {
+        Identity::Id(id) => users.find_by_id(id).await,
+        Identity::Name(name) => users.find_by_name(&name).await,
+        Identity::Email(email) => users.find_by_email(&email).await,
+    };
+
+    // 用户不存在是正常情况, 其他错误需要排查
+    user.map_err(|err| tracing::error!(%err, "failed to look up user"))
+        .ok()
+        .flatten()
+        .ok_or(AuthError::WrongCredentials)
+}
 
-    let id: i32 = row.get(0);
-    let name: String = row.get(1);
-    let password_hash: String = row.get(2);
-    if !bcrypt::verify(payload.password, &password_hash).map_err(|_| AuthError::WrongCredentials)? {
+/// 用户不存在时用于校验的散列, 与真实用户的散列代价相同
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    StringPassword::<UserPasswordProperties>::new("dummy password".to_string())
+        .hash_with_salt()
+        .expect("failed to hash dummy password")
+});
+
+/// 按身份查找用户并校验口令
+/// 
+/// 连续失败达到 [`AuthConfig::lockout_threshold`] 次后锁定账户, 
+/// 锁定期间即使口令正确也返回 [`AuthError::AccountLocked`]; 
+/// `trusted` 为 true 时(请求来自可信网段)既不检查锁定也不累计失败次数
+pub async fn verify_credentials(
+    users: &dyn UserRepository, config: &AuthConfig, identity: Identity, password: &str, trusted: bool
+) -> Result<User, AuthError> {
+    let user = match find_user_by_identity(users, identity).await {
+        Ok(user) => user,
+        Err(err) => {
+            // 用户不存在时同样执行一次口令校验, 使响应时间与口令错误时相近, 
+            // 避免通过响应时间枚举用户名
+            let _ = verify_password(password, &DUMMY_HASH);
+            return Err(err);
+        }
+    };
+    // 散列无法解析说明数据库中的数据有误, 不能当作口令错误, 否则该用户将无法登录且无从排查
+    let verified = verify_password(password, &user.password_hash).map_err(|err| {
+        tracing::error!(id = %user.id, %err, "stored password hash is malformed");
+        AuthError::Internal
+    })?;
+    let now = chrono::Utc::now().naive_utc();
+    if !trusted && user.locked_until.is_some_and(|locked_until| locked_until > now) {
+        return Err(AuthError::AccountLocked);
+    }
+    if !verified {
+        if !trusted {
+            // 达到阈值时锁定账户并清零计数
+            let locked_until = now + chrono::Duration::seconds(config.lockout_cooldown);
+            if let Err(err) = users.record_failed_login(user.id, config.lockout_threshold, locked_until).await {
+                tracing::error!(id = %user.id, %err, "failed to record failed login");
+            }
+        }
         return Err(AuthError::WrongCredentials);
     }
-    let exp = chrono::Utc::now().timestamp() + 3600;
-    let claims = Claims {
-        id,
-        name,
-        exp,
-    };
+    // 口令正确后才说明账户状态, 不向猜测口令的人透露
+    if user.status != UserStatus::Active {
+        return Err(AuthError::AccountInactive);
+    }
+    let mut user = user;
+    if user.failed_login_count > 0 || user.locked_until.is_some() {
+        if let Err(err) = users.reset_failed_logins(user.id).await {
+            tracing::warn!(id = %user.id, %err, "failed to reset failed login count");
+        }
+    }
+    if needs_rehash::<UserPasswordProperties>(&user.password_hash) {
+        // 借此次登录透明地升级散列, 失败时不影响登录
+        match rehash_password(users, user.id, password).await {
+            Ok(password_hash) => user.password_hash = password_hash,
+            Err(err) => tracing::warn!(id = %user.id, %err, "failed to upgrade password hash"),
+        }
+    }
+    Ok(user)
+}
+
+async fn rehash_password(users: &dyn UserRepository, id: UserId, password: &str) -> anyhow::Result<String> {
+    let password_hash = StringPassword::<UserPasswordProperties>::new(password.to_string())
+        .hash_with_random_salt()?;
+    users.replace_password_hash(id, password_hash.clone()).await?;
+    Ok(password_hash)
+}
+
+async fn refresh(
+    State(pool): State<Pool>, State(keys): State<Arc<Keys>>, State(config): State<AuthConfig>, 
+    ApiJson(payload): ApiJson<RefreshPayload>
+) -> Result<(AppendHeaders<Vec<(HeaderName, String)>>, Json<AuthBody>), AuthError> {
+    let row = sqlx::query(
+            "SELECT refresh_token.id, user.id, user.name, user.role FROM refresh_token \
+            JOIN user ON user.id = refresh_token.user_id AND user.deleted_at IS NULL AND user.status = 'active' \
+            WHERE refresh_token.token_hash=? AND refresh_token.expires_at > ?"
+        )
+        .bind(hash_refresh_token(&payload.refresh_token))
+        .bind(chrono::Utc::now().naive_utc())
+        .fetch_optional(&pool)
+        .await
+        .map_err(|err| {
+            tracing::error!(%err, "failed to look up refresh token");
+            AuthError::InvalidRefreshToken
+        })?
+        .ok_or(AuthError::InvalidRefreshToken)?;
+
+    let token_id: i32 = row.get(0);
+    let id: UserId = row.get(1);
+    let name: String = row.get(2);
+    let role: String = row.get(3);
+
+    // 刷新令牌只能使用一次, 删除失败说明已被并发使用
+    let deleted = sqlx::query("DELETE FROM refresh_token WHERE id=?")
+        .bind(token_id)
+        .execute(&pool)
+        .await
+        .map_err(|err| {
+            tracing::error!(%err, "failed to consume refresh token");
+            AuthError::TokenCreation
+        })?;
+    if deleted.rows_affected() == 0 {
+        return Err(AuthError::InvalidRefreshToken);
+    }
+
+    let body = issue_tokens(&pool, &keys, &config, id, name, role).await?;
+    Ok((token_cookies(&config, &body)?, Json(body)))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordPayload {
+    old_password: String,
+    new_password: UserPassword,
+}
+
+impl std::fmt::Debug for ChangePasswordPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChangePasswordPayload")
+            .field("old_password", &format_args!("***"))
+            .field("new_password", &format_args!("***"))
+            .finish()
+    }
+}
+
+/// 修改当前用户的口令, 成功后注销当前访问令牌, 此前签发给该用户的其他访问令牌与所有刷新令牌一并作废
+async fn change_password(
+    State(pool): State<Pool>, State(users): State<Arc<dyn UserRepository>>, State(config): State<AuthConfig>, claims: Claims, 
+    ApiJson(payload): ApiJson<ChangePasswordPayload>
+) -> Result<String, ApiError> {
+    verify_credentials(users.as_ref(), &config, Identity::Id(claims.id), &payload.old_password, false)
+        .await
+        .map_err(|err| match err {
+            AuthError::WrongCredentials => ApiError::new(StatusCode::UNAUTHORIZED, "wrong password"),
+            AuthError::AccountLocked => ApiError::new(StatusCode::LOCKED, err.to_string()),
+            AuthError::AccountInactive => ApiError::new(StatusCode::FORBIDDEN, err.to_string()),
+            AuthError::Unavailable => ApiError::new(StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+            _ => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal server error"),
+        })?;
+    payload.new_password.check_strength().map_err(weak_password)?;
+    let password_hash = payload.new_password.hash_with_random_salt().map_err(internal_error)?;
+
+    let expires_at = chrono::DateTime::from_timestamp(claims.exp, 0)
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "invalid token"))?
+        .naive_utc();
+    let mut tx = pool.begin().await.map_err(database_error)?;
+    // 其他会话持有的访问令牌随之失效, 见 `Claims` 提取器
+    sqlx::query("UPDATE user SET password_hash=?, tokens_valid_after=? WHERE id=? AND deleted_at IS NULL")
+        .bind(password_hash)
+        .bind(chrono::Utc::now().naive_utc())
+        .bind(claims.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    sqlx::query("DELETE FROM refresh_token WHERE user_id=?")
+        .bind(claims.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    sqlx::query("INSERT INTO revoked_token (jti, expires_at) VALUES (?,?)")
+        .bind(&claims.jti)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    tracing::info!(id = %claims.id, "password changed");
+    Ok("ok".to_string())
+}
+
+async fn logout(
+    State(pool): State<Pool>, State(config): State<AuthConfig>, claims: Claims
+) -> Result<(AppendHeaders<Vec<(HeaderName, String)>>, String), AuthError> {
+    let expires_at = chrono::DateTime::from_timestamp(claims.exp, 0)
+        .ok_or(AuthError::InvalidToken)?
+        .naive_utc();
+    sqlx::query("INSERT INTO revoked_token (jti, expires_at) VALUES (?,?)")
+        .bind(&claims.jti)
+        .bind(expires_at)
+        .execute(&pool)
+        .await?;
+
+    // 已过期的令牌无论如何都会被拒绝, 无需继续记录
+    sqlx::query("DELETE FROM revoked_token WHERE expires_at < ?")
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(&pool)
+        .await?;
+
+    Ok((clear_token_cookies(&config), "ok".to_string()))
+}
+
+/// 签发访问令牌, 并生成一个新的刷新令牌存入数据库
+async fn issue_tokens(
+    pool: &Pool, keys: &impl AuthKeys, config: &AuthConfig, id: UserId, name: String, role: String
+) -> Result<AuthBody, AuthError> {
+    let token = sign_access_token(keys, config, id, name, role)?;
+
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|err| {
+        tracing::error!(%err, "failed to generate refresh token");
+        AuthError::TokenCreation
+    })?;
+    let refresh_token = to_hex(&bytes);
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(config.refresh_token_ttl);
+
+    sqlx::query("INSERT INTO refresh_token (user_id, token_hash, expires_at) VALUES (?,?,?)")
+        .bind(id)
+        .bind(hash_refresh_token(&refresh_token))
+        .bind(expires_at)
+        .execute(pool)
+        .await
+        .map_err(|err| {
+            tracing::error!(%err, "failed to store refresh token");
+            AuthError::TokenCreation
+        })?;
+
+    Ok(AuthBody::new(token, refresh_token))
+}
+
+/// 签发有效期为 `config.token_ttl` 秒的访问令牌
+fn sign_access_token(
+    keys: &impl AuthKeys, config: &AuthConfig, id: UserId, name: String, role: String
+) -> Result<String, AuthError> {
+    let claims = Claims::new(id, name, role, config, chrono::Duration::seconds(config.token_ttl));
+    // 有效期在加载配置时已检查, 这里兜底, 不签发已过期的令牌
+    if claims.exp <= claims.iat {
+        tracing::error!(token_ttl = config.token_ttl, "refusing to issue an already expired token");
+        return Err(AuthError::TokenCreation);
+    }
 
     // Create the authorization token
-    let token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, keys.get_encoding())
-        .map_err(|_| AuthError::TokenCreation)?;
+    let mut header = jsonwebtoken::Header::new(keys.get_algorithm());
+    header.kid = Some(keys.get_kid().to_string());
+    jsonwebtoken::encode(&header, &claims, keys.get_encoding())
+        .map_err(|err| {
+            tracing::error!(%err, "failed to sign access token");
+            AuthError::TokenCreation
+        })
+}
+
+/// 数据库中只保存刷新令牌的 SHA-256 摘要
+fn hash_refresh_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
 
-    return Ok(Json(AuthBody::new(token)));
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
-async fn protected(claims: Claims) -> Result<String, AuthError> {
+#[utoipa::path(
+    get,
+    path = "/auth/protected",
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (
+            status = 200, 
+            description = "当前令牌中的用户信息; `Accept` 包含 `application/json` 时返回 JSON, 否则返回文本", 
+            content((String = "text/plain"), (Object = "application/json"))
+        ),
+        (status = 401, description = "令牌缺失或无效", body = ErrorBody),
+    )
+)]
+pub(crate) async fn protected(headers: HeaderMap, claims: Claims) -> Result<axum::response::Response, AuthError> {
+    // 未指定或不接受 JSON 时保持原来的文本格式
+    let wants_json = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| range.split(';').next().is_some_and(|media| media.trim().eq_ignore_ascii_case("application/json")));
+    if wants_json {
+        return Ok(Json(claims).into_response());
+    }
     // Send the protected data to the user
     Ok(format!(
         "Welcome to the protected area :)\nYour data:\n{claims}",
+    ).into_response())
+}
+
+/// 当前用户的完整信息, 从数据库读取, 因此能反映签发令牌后的修改
+async fn me(State(pool): State<Pool>, claims: Claims) -> Result<Json<UserPublic>, ApiError> {
+    fetch_user(&pool, claims.id)
+        .await
+        .map_err(database_error)?
+        .map(|user| Json(user.into()))
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "user not found"))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenInfo {
+    id: UserId,
+    name: String,
+    exp: i64,
+    /// 距离过期的秒数, 客户端据此提前刷新令牌
+    seconds_remaining: i64,
+}
+
+/// 当前访问令牌的有效期, 只读取令牌本身, 不访问数据库
+async fn token_info(claims: Claims) -> Json<TokenInfo> {
+    // 在容许的时钟偏差内已过期的令牌仍可通过校验, 此时剩余时间记为 0
+    let seconds_remaining = (claims.exp - chrono::Utc::now().timestamp()).max(0);
+    Json(TokenInfo { 
+        id: claims.id, 
+        name: claims.name, 
+        exp: claims.exp, 
+        seconds_remaining,
+    })
+}
+
+async fn admin(RequireRole(claims, _): RequireRole<role::Admin>) -> Result<String, AuthError> {
+    Ok(format!(
+        "Welcome to the admin area :)\nYour data:\n{claims}",
     ))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{body::Body, http::{header::{ACCEPT, CONTENT_TYPE, WWW_AUTHENTICATE}, HeaderMap, Request, StatusCode}, response::IntoResponse};
+
+    use super::{
+        constant_time_eq, protected, sign_access_token, token_from_cookie, AuthBody, AuthConfig, AuthError, AuthPayload, 
+        ChangePasswordPayload, Claims, RefreshPayload, ACCESS_TOKEN_COOKIE, CSRF_COOKIE, CSRF_HEADER
+    };
+    use crate::{
+        database::Pool, 
+        model::{repository::{InMemoryUsers, NewUser, UserRepository}, user::{UserId, UserPassword, UserStatus}},
+        server::state::AppState,
+        testing::{json_request, lazy_pool, login, seed_user, send, test_app, test_state, test_state_with, TEST_SECRET}, 
+        util::keys::{AuthKeys, Keys}
+    };
+
+    /// 以当前时间签发、`exp` 与 `iat` 相对当前时间偏移给定秒数的令牌
+    fn claims_at(exp_offset: i64, iat_offset: i64) -> Claims {
+        let mut claims = Claims::new(
+            UserId(1), 
+            "alice".to_string(), 
+            "user".to_string(), 
+            &AuthConfig::default(), 
+            chrono::Duration::zero()
+        );
+        let now = chrono::Utc::now().timestamp();
+        claims.exp = now + exp_offset;
+        claims.iat = now + iat_offset;
+        claims
+    }
+
+    fn challenge(err: AuthError) -> (StatusCode, Option<String>) {
+        let response = err.into_response();
+        let challenge = response.headers()
+            .get(WWW_AUTHENTICATE)
+            .map(|value| value.to_str().unwrap().to_string());
+        (response.status(), challenge)
+    }
+
+    #[test]
+    fn invalid_token_is_401_with_error_code() {
+        assert_eq!(
+            challenge(AuthError::InvalidToken), 
+            (StatusCode::UNAUTHORIZED, Some("Bearer realm=\"auto-planning-backend\", error=\"invalid_token\"".to_string()))
+        );
+    }
+
+    #[test]
+    fn missing_token_is_401_without_error_code() {
+        assert_eq!(
+            challenge(AuthError::MissingToken), 
+            (StatusCode::UNAUTHORIZED, Some("Bearer realm=\"auto-planning-backend\"".to_string()))
+        );
+        assert_eq!(challenge(AuthError::WrongCredentials).1.as_deref(), Some("Bearer realm=\"auto-planning-backend\""));
+    }
+
+    #[test]
+    fn forbidden_reports_insufficient_scope() {
+        let (status, challenge) = challenge(AuthError::Forbidden);
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(challenge.unwrap().ends_with("error=\"insufficient_scope\""));
+    }
+
+    #[test]
+    fn non_auth_failures_have_no_challenge() {
+        assert_eq!(challenge(AuthError::MissingCredentials), (StatusCode::BAD_REQUEST, None));
+        assert_eq!(challenge(AuthError::AccountInactive), (StatusCode::FORBIDDEN, None));
+        assert_eq!(challenge(AuthError::CsrfMismatch), (StatusCode::FORBIDDEN, None));
+    }
+
+    #[test]
+    fn database_errors_are_not_token_errors() {
+        assert!(matches!(AuthError::from(sqlx::Error::PoolTimedOut), AuthError::Unavailable));
+        assert_eq!(challenge(AuthError::Unavailable), (StatusCode::SERVICE_UNAVAILABLE, None));
+        assert!(matches!(AuthError::from(sqlx::Error::RowNotFound), AuthError::Internal));
+    }
+
+    /// 带有访问令牌 cookie 与 CSRF cookie 的请求, `csrf_header` 为 `X-CSRF-Token` 的值
+    fn cookie_request(method: &str, csrf_header: Option<&str>) -> axum::http::request::Parts {
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/auth/logout")
+            .header("cookie", format!("{ACCESS_TOKEN_COOKIE}=the-token; {CSRF_COOKIE}=csrf-value"));
+        if let Some(csrf_header) = csrf_header {
+            request = request.header(CSRF_HEADER, csrf_header);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn cookie_token_is_read_for_safe_methods_without_csrf() {
+        assert_eq!(token_from_cookie(&cookie_request("GET", None)).unwrap(), "the-token");
+    }
+
+    #[test]
+    fn cookie_token_requires_matching_csrf_header_for_writes() {
+        assert!(matches!(token_from_cookie(&cookie_request("POST", None)), Err(AuthError::CsrfMismatch)));
+        assert!(matches!(token_from_cookie(&cookie_request("POST", Some("csrf-valuf"))), Err(AuthError::CsrfMismatch)));
+        assert_eq!(token_from_cookie(&cookie_request("POST", Some("csrf-value"))).unwrap(), "the-token");
+    }
+
+    #[test]
+    fn missing_cookie_is_missing_token() {
+        let parts = Request::builder().method("POST").body(()).unwrap().into_parts().0;
+        assert!(matches!(token_from_cookie(&parts), Err(AuthError::MissingToken)));
+    }
+
+    #[tokio::test]
+    async fn cookie_auth_rejects_write_without_csrf_header() {
+        let auth_config = AuthConfig { cookie_auth: true, ..Default::default() };
+        let app = test_app(AppState { auth_config, ..test_state(lazy_pool()) });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/auth/logout")
+            .header("cookie", format!("{ACCESS_TOKEN_COOKIE}=the-token; {CSRF_COOKIE}=csrf-value"))
+            .body(Body::empty())
+            .unwrap();
+        // CSRF 检查在解码令牌与查询数据库之前
+        let (status, headers, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!headers.contains_key(WWW_AUTHENTICATE));
+        assert_eq!(body["error"], "CSRF token missing or mismatched");
+    }
+
+    /// 以给定的 `Accept` 调用 `/auth/protected` 的处理函数, 返回响应的 `Content-Type` 与正文
+    async fn protected_with(accept: Option<&str>) -> (String, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(ACCEPT, accept.parse().unwrap());
+        }
+        let response = protected(headers, claims_at(60, 0)).await.unwrap();
+        let content_type = response.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn protected_returns_text_without_accept() {
+        let (content_type, body) = protected_with(None).await;
+        assert!(content_type.starts_with("text/plain"));
+        assert!(body.starts_with("Welcome to the protected area"));
+    }
+
+    #[tokio::test]
+    async fn protected_returns_text_for_text_plain() {
+        let (content_type, body) = protected_with(Some("text/plain")).await;
+        assert!(content_type.starts_with("text/plain"));
+        assert!(body.contains("Name: alice"));
+    }
+
+    #[tokio::test]
+    async fn protected_returns_json_for_application_json() {
+        let (content_type, body) = protected_with(Some("text/html, application/json;q=0.9")).await;
+        assert_eq!(content_type, "application/json");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["name"], "alice");
+        assert_eq!(body["id"], 1);
+    }
+
+    /// 断言 `Debug` 输出以 `***` 代替了 `secret`
+    fn assert_redacted(value: &impl std::fmt::Debug, secret: &str) {
+        let debug = format!("{value:?}");
+        assert!(debug.contains("***"), "{debug}");
+        assert!(!debug.contains(secret), "{debug}");
+    }
+
+    #[test]
+    fn payload_debug_output_redacts_secrets() {
+        let payload: AuthPayload = serde_json::from_value(serde_json::json!({ "name": "alice", "password": "hunter2 secret" })).unwrap();
+        assert_redacted(&payload, "hunter2");
+        let payload: RefreshPayload = serde_json::from_value(serde_json::json!({ "refreshToken": "opaque-refresh-token" })).unwrap();
+        assert_redacted(&payload, "opaque-refresh-token");
+        let payload: ChangePasswordPayload = serde_json::from_value(
+            serde_json::json!({ "oldPassword": "hunter2 secret", "newPassword": "Staple-battery-horse-9" })
+        ).unwrap();
+        assert_redacted(&payload, "hunter2");
+        assert_redacted(&payload, "Staple-battery-horse-9");
+    }
+
+    #[test]
+    fn auth_body_uses_camel_case_keys() {
+        let value = serde_json::to_value(AuthBody::new("access".to_string(), "refresh".to_string())).unwrap();
+        assert_eq!(value, serde_json::json!({ "accessToken": "access", "refreshToken": "refresh", "tokenType": "Bearer" }));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_contents() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"csrf-token", b"csrf-token"));
+        assert!(!constant_time_eq(b"csrf-token", b"csrf-tokem"));
+        assert!(!constant_time_eq(b"csrf-token", b"Csrf-token"));
+    }
+
+    #[test]
+    fn constant_time_eq_rejects_different_lengths() {
+        assert!(!constant_time_eq(b"csrf", b"csrf-token"));
+        assert!(!constant_time_eq(b"csrf-token", b""));
+    }
+
+    #[test]
+    fn unexpired_token_is_valid() {
+        assert!(!claims_at(60, 0).is_expired(0));
+    }
+
+    #[test]
+    fn expired_token_is_rejected_outside_leeway() {
+        assert!(claims_at(-60, -120).is_expired(0));
+        assert!(claims_at(-60, -120).is_expired(30));
+        assert!(!claims_at(-60, -120).is_expired(120));
+        // 到达 `exp` (加上 leeway) 时即已过期
+        assert!(claims_at(0, -120).is_expired(0));
+        assert!(claims_at(-30, -120).is_expired(30));
+        // 留出几秒, 以免测试执行期间时钟跨过一秒
+        assert!(!claims_at(5, -120).is_expired(0));
+        assert!(!claims_at(-25, -120).is_expired(30));
+    }
+
+    /// 以 `state` 的密钥签名 `claims`, 再经由 `Claims` 提取器校验
+    async fn extract_signed(state: &AppState, claims: &Claims) -> Result<Claims, AuthError> {
+        use axum::extract::FromRequestParts;
+
+        let mut header = jsonwebtoken::Header::new(state.keys.get_algorithm());
+        header.kid = Some(state.keys.get_kid().to_string());
+        let token = jsonwebtoken::encode(&header, claims, state.keys.get_encoding()).unwrap();
+        let (mut parts, _) = Request::get("/")
+            .header("authorization", format!("Bearer {token}"))
+            .body(())
+            .unwrap()
+            .into_parts();
+        Claims::from_request_parts(&mut parts, state).await
+    }
+
+    // 签发者与受众在查询数据库之前校验, 因此不需要真实的数据库
+    #[tokio::test]
+    async fn token_with_wrong_issuer_is_rejected() {
+        let state = test_state(lazy_pool());
+        let mut claims = claims_at(3600, 0);
+        claims.iss = "someone-else".to_string();
+        let err = extract_signed(&state, &claims).await.unwrap_err();
+        assert!(matches!(err, AuthError::InvalidToken), "{err:?}");
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn token_with_wrong_audience_is_rejected() {
+        let state = test_state(lazy_pool());
+        let mut claims = claims_at(3600, 0);
+        claims.aud = "someone-else".to_string();
+        let err = extract_signed(&state, &claims).await.unwrap_err();
+        assert!(matches!(err, AuthError::InvalidToken), "{err:?}");
+        assert_eq!(err.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn authorize_requires_exactly_one_identity() {
+        let app = test_app(test_state_with(lazy_pool(), Arc::new(InMemoryUsers::default())));
+        let authorize = |body| json_request("POST", "/auth/authorize", body);
+
+        let (status, _, body) = send(app.clone(), authorize(serde_json::json!({ "password": "secret" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], 400);
+        let two = serde_json::json!({ "name": "alice", "email": "alice@example.com", "password": "secret" });
+        assert_eq!(send(app.clone(), authorize(two)).await.0, StatusCode::BAD_REQUEST);
+        // 只给出一个身份时才会查找用户; 用户不存在, 因此是口令错误而不是缺少凭据
+        for one in [
+            serde_json::json!({ "id": 1, "password": "secret" }), 
+            serde_json::json!({ "name": "alice", "password": "secret" }), 
+            serde_json::json!({ "email": "alice@example.com", "password": "secret" }), 
+        ] {
+            assert_eq!(send(app.clone(), authorize(one)).await.0, StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[test]
+    fn access_token_expires_after_configured_ttl() {
+        let keys = Keys::new(TEST_SECRET);
+        let config = AuthConfig { token_ttl: 120, ..AuthConfig::default() };
+        let token = sign_access_token(&keys, &config, UserId(1), "alice".to_string(), "user".to_string()).unwrap();
+
+        let mut validation = jsonwebtoken::Validation::new(keys.get_algorithm());
+        validation.set_issuer(&[&config.issuer]);
+        validation.set_audience(&[&config.audience]);
+        let claims = jsonwebtoken::decode::<Claims>(&token, keys.get_decoding(), &validation).unwrap().claims;
+        assert_eq!(claims.exp - claims.iat, 120);
+        assert!((claims.exp - chrono::Utc::now().timestamp() - 120).abs() <= 1);
+    }
+
+    #[test]
+    fn non_positive_ttl_is_not_signed() {
+        let config = AuthConfig { token_ttl: 0, ..AuthConfig::default() };
+        let result = sign_access_token(&Keys::new(TEST_SECRET), &config, UserId(1), "alice".to_string(), "user".to_string());
+        assert!(matches!(result, Err(AuthError::TokenCreation)));
+    }
+
+    #[test]
+    fn token_issued_in_future_is_rejected_outside_leeway() {
+        assert!(!claims_at(3600, 0).is_issued_in_future(0));
+        assert!(claims_at(3600, 60).is_issued_in_future(30));
+        assert!(!claims_at(3600, 60).is_issued_in_future(120));
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn authorize_then_access_protected(pool: Pool) {
+        seed_user(&pool, "alice", "correct horse battery", "user").await;
+        let app = test_app(test_state(pool));
+
+        let token = login(app.clone(), "alice", "correct horse battery").await;
+        let request = Request::builder()
+            .uri("/auth/protected")
+            .header("authorization", format!("Bearer {token}"))
+            .header("accept", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["name"], "alice");
+        assert_eq!(body["role"], "user");
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn change_password_invalidates_other_tokens(pool: Pool) {
+        seed_user(&pool, "alice", "correct horse battery", "user").await;
+        let app = test_app(test_state(pool));
+        let protected = |token: &str| Request::builder()
+            .uri("/auth/protected")
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        let other = login(app.clone(), "alice", "correct horse battery").await;
+        let current = login(app.clone(), "alice", "correct horse battery").await;
+        // `iat` 只精确到秒, 与修改口令不能落在同一秒
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let mut request = json_request(
+            "POST", 
+            "/auth/change-password", 
+            serde_json::json!({ "oldPassword": "correct horse battery", "newPassword": "Staple-battery-horse-9" })
+        );
+        request.headers_mut().insert("authorization", format!("Bearer {current}").parse().unwrap());
+        let (status, _, body) = send(app.clone(), request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let (status, _, _) = send(app.clone(), protected(&other)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let fresh = login(app.clone(), "alice", "Staple-battery-horse-9").await;
+        let (status, _, _) = send(app, protected(&fresh)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn authorize_rejects_wrong_password(pool: Pool) {
+        seed_user(&pool, "alice", "correct horse battery", "user").await;
+        let app = test_app(test_state(pool));
+
+        let (status, _, _) = send(
+            app, 
+            json_request("POST", "/auth/authorize", serde_json::json!({ "name": "alice", "password": "wrong" }))
+        ).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn authorize_rejects_suspended_user_in_memory() {
+        let users = Arc::new(InMemoryUsers::default());
+        let password_hash = UserPassword::new("correct horse battery".to_string()).hash_with_random_salt().unwrap();
+        let user = NewUser { name: "alice", email: None, password_hash, idempotency_key: None };
+        users.create(user, None).await.unwrap();
+        let id = users.find_by_name("alice").await.unwrap().unwrap().id;
+        users.set_status(id, UserStatus::Suspended);
+        let app = test_app(test_state_with(lazy_pool(), users));
+
+        let authorize = |password: &str| json_request(
+            "POST", "/auth/authorize", serde_json::json!({ "name": "alice", "password": password })
+        );
+        let (status, _, body) = send(app.clone(), authorize("correct horse battery")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "Account is not active");
+        // 口令错误时不透露账户状态
+        assert_eq!(send(app, authorize("wrong")).await.0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn authorize_rejects_suspended_user(pool: Pool) {
+        let id = seed_user(&pool, "alice", "correct horse battery", "user").await;
+        sqlx::query("UPDATE user SET status='suspended' WHERE id=?")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let app = test_app(test_state(pool));
+
+        let (status, _, body) = send(
+            app, 
+            json_request("POST", "/auth/authorize", serde_json::json!({ "name": "alice", "password": "correct horse battery" }))
+        ).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "Account is not active");
+    }
+
+    #[sqlx::test]
+    #[ignore = "needs a MariaDB/MySQL server, see src/testing.rs"]
+    async fn change_password_reports_inactive_account(pool: Pool) {
+        let id = seed_user(&pool, "alice", "correct horse battery", "user").await;
+        let app = test_app(test_state(pool.clone()));
+        let token = login(app.clone(), "alice", "correct horse battery").await;
+        sqlx::query("UPDATE user SET status='suspended' WHERE id=?")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut request = json_request(
+            "POST", 
+            "/auth/change-password", 
+            serde_json::json!({ "oldPassword": "correct horse battery", "newPassword": "Staple-battery-horse-9" })
+        );
+        request.headers_mut().insert("authorization", format!("Bearer {token}").parse().unwrap());
+        let (status, _, body) = send(app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "Account is not active");
+    }
+}
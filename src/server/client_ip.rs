@@ -0,0 +1,226 @@
+/*
+*   server::client_ip
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    convert::Infallible, 
+    net::{IpAddr, SocketAddr}, 
+    str::FromStr, 
+    sync::Arc
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts}, 
+    http::{Extensions, HeaderMap}
+};
+
+/// 形如 `10.0.0.0/8` 或 `fd00::/8` 的网段, 不带前缀长度时表示单个地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// IPv4 映射的 IPv6 地址(`::ffff:a.b.c.d`)按 IPv4 地址比较
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCidr(String);
+
+impl std::fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid CIDR `{}`, expected e.g. `10.0.0.0/8`", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_string());
+        let (network, prefix) = match s.trim().split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let network = IpAddr::from_str(network).map_err(|_| invalid())?.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+}
+
+/// 如何确定客户端地址, 以及哪些地址可以信任
+#[derive(Debug, Clone, Default)]
+pub struct ClientIpConfig {
+    /// 是否采用 `X-Forwarded-For`; 只有服务位于会追加该头的反向代理之后时才能开启, 
+    /// 否则客户端可以随意伪造地址, 绕过限流或冒充可信网段
+    pub trust_forwarded_for: bool,
+    /// 来自这些网段的请求不受登录限流与失败锁定的约束
+    pub trusted_networks: Vec<Cidr>,
+}
+
+impl ClientIpConfig {
+    /// 获取客户端 IP; 信任 `X-Forwarded-For` 时取其中最后一个地址, 
+    /// 即紧邻的反向代理看到的对端, 更早的地址可由客户端任意填写;
+    /// 否则使用连接的对端地址
+    /// 
+    /// 客户端可以自己发送一个 `X-Forwarded-For`, 代理会另起一行追加, 
+    /// 因此要取最后一行中的最后一项, 而不是第一行的
+    /// 
+    /// 后者需要以 `into_make_service_with_connect_info` 启动服务
+    pub fn client_ip(&self, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+        let forwarded = self.trust_forwarded_for
+            .then(|| headers.get_all("x-forwarded-for").iter().last())
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        forwarded.or_else(|| {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_networks.iter().any(|network| network.contains(ip))
+    }
+}
+
+/// 客户端是否来自可信网段的提取器, 地址未知时不视为可信
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddr {
+    /// 是否来自 [`ClientIpConfig::trusted_networks`]
+    pub trusted: bool,
+}
+
+impl<S> FromRequestParts<S> for ClientAddr
+where 
+    S: Send + Sync, 
+    Arc<ClientIpConfig>: FromRef<S>
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut axum::http::request::Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<ClientIpConfig>::from_ref(state);
+        let ip = config.client_ip(&parts.headers, &parts.extensions);
+        Ok(Self { trusted: ip.is_some_and(|ip| config.is_trusted(ip)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_parses_networks_and_single_addresses() {
+        assert_eq!(cidr("10.0.0.0/8"), Cidr { network: ip("10.0.0.0"), prefix: 8 });
+        assert_eq!(cidr(" 192.168.1.1 "), Cidr { network: ip("192.168.1.1"), prefix: 32 });
+        assert_eq!(cidr("fd00::/8"), Cidr { network: ip("fd00::"), prefix: 8 });
+        assert_eq!(cidr("::1"), Cidr { network: ip("::1"), prefix: 128 });
+        // IPv4 映射的 IPv6 地址按 IPv4 地址处理
+        assert_eq!(cidr("::ffff:10.0.0.1"), Cidr { network: ip("10.0.0.1"), prefix: 32 });
+    }
+
+    #[test]
+    fn cidr_rejects_invalid_input() {
+        for s in ["", "10.0.0.0/", "10.0.0.0/33", "fd00::/129", "10.0.0/8", "example.com", "10.0.0.0/-1"] {
+            assert!(s.parse::<Cidr>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn cidr_contains_addresses_in_network() {
+        let network = cidr("10.1.0.0/16");
+        assert!(network.contains(ip("10.1.0.0")));
+        assert!(network.contains(ip("10.1.255.255")));
+        assert!(network.contains(ip("::ffff:10.1.2.3")));
+        assert!(!network.contains(ip("10.2.0.0")));
+        assert!(!network.contains(ip("fd00::1")));
+
+        assert!(cidr("fd00::/8").contains(ip("fdff::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe00::1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.1")));
+        assert!(cidr("127.0.0.1").contains(ip("127.0.0.1")));
+        assert!(!cidr("127.0.0.1").contains(ip("127.0.0.2")));
+    }
+
+    fn forwarded(lines: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for line in lines {
+            headers.append("x-forwarded-for", HeaderValue::from_str(line).unwrap());
+        }
+        headers
+    }
+
+    fn with_peer(ip: &str) -> Extensions {
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 1234)));
+        extensions
+    }
+
+    #[test]
+    fn forwarded_for_uses_last_entry_of_last_line() {
+        let config = ClientIpConfig { trust_forwarded_for: true, ..Default::default() };
+        let peer = with_peer("10.0.0.1");
+        let headers = forwarded(&["1.1.1.1, 2.2.2.2"]);
+        assert_eq!(config.client_ip(&headers, &peer), Some(ip("2.2.2.2")));
+        // 客户端伪造的一行在前, 代理追加的一行在后
+        let headers = forwarded(&["1.1.1.1", "3.3.3.3"]);
+        assert_eq!(config.client_ip(&headers, &peer), Some(ip("3.3.3.3")));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_unless_trusted() {
+        let config = ClientIpConfig::default();
+        let headers = forwarded(&["1.1.1.1"]);
+        assert_eq!(config.client_ip(&headers, &with_peer("10.0.0.1")), Some(ip("10.0.0.1")));
+        assert_eq!(config.client_ip(&headers, &Extensions::new()), None);
+    }
+}
@@ -0,0 +1,91 @@
+/*
+*   server::debug
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use axum::{routing::get, Json, Router};
+use serde_json::{json, Value};
+
+use crate::config::Config;
+
+/// 在 `/debug/config` 输出生效的配置, 便于排查部署问题
+/// 
+/// 只在启用 `dev` feature 的调试构建中编译, 发布构建即使启用该 feature 也不包含此接口
+pub fn debug_router<S>(config: &Config) -> Router<S>
+where 
+    S: Clone + Send + Sync + 'static
+{
+    let snapshot = redacted(config);
+    Router::new().route("/debug/config", get(move || std::future::ready(Json(snapshot))))
+}
+
+// 逐项列出可以公开的字段; 密钥与口令只说明是否已设置, 新增的配置项不会被自动带出
+fn redacted(config: &Config) -> Value {
+    let Config { database, jwt, server, users, .. } = config;
+    let auth = config.auth().ok();
+    let rate_limit = config.rate_limit().ok();
+    let hashing = config.password_hashing().ok();
+    json!({
+        "database": {
+            "user": database.user,
+            "password": database.password.as_ref().map(|_| "***"),
+            "host": database.host,
+            "port": database.port,
+            "name": database.name,
+            "socket": database.socket,
+        },
+        "jwt": {
+            "algorithm": format!("{:?}", jwt.algorithm),
+            "secret": jwt.secret.as_ref().map(|_| "***"),
+            "secret_file": jwt.secret_file,
+            "previous_secrets": jwt.previous_secrets.len(),
+            "private_key_file": jwt.private_key_file,
+            "public_key_file": jwt.public_key_file,
+            "token_ttl_secs": auth.as_ref().map(|auth| auth.token_ttl),
+            "refresh_token_ttl_secs": auth.as_ref().map(|auth| auth.refresh_token_ttl),
+            "issuer": auth.as_ref().map(|auth| &auth.issuer),
+            "audience": auth.as_ref().map(|auth| &auth.audience),
+            "leeway_secs": auth.as_ref().map(|auth| auth.leeway),
+            "cookie_auth": auth.as_ref().map(|auth| auth.cookie_auth),
+        },
+        "server": {
+            "bind": server.bind.to_string(),
+            "max_body_bytes": server.max_body_bytes,
+            "tls_cert": server.tls_cert,
+            "tls_key": server.tls_key,
+            "csp": server.csp,
+            "trust_forwarded_for": server.trust_forwarded_for,
+            "trusted_networks": server.trusted_networks,
+            "cors_allowed_origins": server.cors_allowed_origins,
+        },
+        "lockout": {
+            "threshold": auth.as_ref().map(|auth| auth.lockout_threshold),
+            "cooldown_secs": auth.as_ref().map(|auth| auth.lockout_cooldown),
+        },
+        "users": {
+            "max_users": users.max_users,
+            "idempotency_ttl_secs": config.users().ok().map(|users| users.idempotency_ttl),
+        },
+        "rate_limit": {
+            "requests": rate_limit.as_ref().map(|limit| limit.requests),
+            "window_secs": rate_limit.as_ref().map(|limit| limit.window.as_secs()),
+        },
+        "password": {
+            "algorithm": hashing.map(|hashing| format!("{:?}", hashing.algorithm)),
+            "cost": hashing.map(|hashing| hashing.cost),
+        },
+    })
+}
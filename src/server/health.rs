@@ -0,0 +1,57 @@
+/*
+*   server::health
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde_json::{json, Value};
+
+use crate::{database::Pool, server::state::AppState};
+
+pub fn health_router() -> Router<AppState> {
+    Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+}
+
+// 存活探针, 不访问数据库
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+// 就绪探针, 数据库不可用时返回 503; 响应体附带连接池状态, 便于排查连接耗尽
+async fn ready(State(pool): State<Pool>) -> (StatusCode, Json<Value>) {
+    let result = sqlx::query("SELECT 1").execute(&pool).await;
+    // 在查询之后读取, 此时查询使用的连接已归还
+    let size = pool.size();
+    let idle = pool.num_idle();
+    let pool_stats = json!({
+        "size": size,
+        "idle": idle,
+        "in_use": (size as usize).saturating_sub(idle),
+    });
+    match result {
+        Ok(_) => (StatusCode::OK, Json(json!({ "status": "ready", "pool": pool_stats }))),
+        // 探针通常无需认证即可访问, 错误详情只记录在日志中
+        Err(err) => {
+            tracing::warn!(%err, "readiness check failed");
+            (
+                StatusCode::SERVICE_UNAVAILABLE, 
+                Json(json!({ "status": "unavailable", "error": "database unavailable", "pool": pool_stats }))
+            )
+        }
+    }
+}
@@ -0,0 +1,68 @@
+/*
+*   server::metrics
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request}, 
+    middleware::Next, 
+    response::Response, 
+    routing::get, 
+    Router
+};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+
+/// 安装全局的 Prometheus 记录器, 之后 `metrics` 宏记录的指标都会汇总到返回的句柄中
+pub fn install_recorder() -> anyhow::Result<PrometheusHandle> {
+    PrometheusBuilder::new()
+        .install_recorder()
+        .map_err(|err| anyhow::anyhow!("failed to install metrics recorder: {err}"))
+}
+
+/// 在 `/metrics` 以 Prometheus 文本格式输出指标
+pub fn metrics_router<S>(handle: PrometheusHandle) -> Router<S>
+where 
+    S: Clone + Send + Sync + 'static
+{
+    Router::new().route("/metrics", get(move || std::future::ready(handle.render())))
+}
+
+/// 按路由记录请求数与处理耗时
+/// 
+/// 使用路由模板(如 `/users/{id}`)而不是实际路径作为标签, 以免标签数量随请求无限增长
+pub async fn track_metrics(request: Request, next: Next) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let latency = start.elapsed().as_secs_f64();
+
+    let labels = [
+        ("method", method), 
+        ("path", path), 
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels).record(latency);
+    response
+}
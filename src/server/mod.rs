@@ -1 +1,12 @@
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod client_ip;
+pub mod cors;
+#[cfg(all(feature = "dev", debug_assertions))]
+pub mod debug;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
+pub mod rate_limit;
+pub mod security_headers;
+pub mod state;
+pub mod trace;
\ No newline at end of file
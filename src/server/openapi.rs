@@ -0,0 +1,72 @@
+/*
+*   server::openapi
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use axum::Router;
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme}, 
+    Modify, OpenApi
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::server::auth::AuthError;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::model::user::create_user,
+        crate::model::user::query_user,
+        crate::model::user::count_users,
+        crate::server::auth::authorize,
+        crate::server::auth::protected,
+    ),
+    components(schemas(AuthError)),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "users", description = "用户管理"),
+        (name = "auth", description = "登录与令牌"),
+    )
+)]
+pub struct ApiDoc;
+
+// 为需要登录的接口声明 Bearer 令牌认证
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build()
+            ),
+        );
+    }
+}
+
+/// 在 `/api-docs/openapi.json` 提供 OpenAPI 文档, 在 `/swagger` 提供 Swagger UI
+pub fn openapi_router<S>() -> Router<S>
+where 
+    S: Clone + Send + Sync + 'static
+{
+    SwaggerUi::new("/swagger")
+        .url("/api-docs/openapi.json", ApiDoc::openapi())
+        .into()
+}
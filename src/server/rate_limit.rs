@@ -0,0 +1,131 @@
+/*
+*   server::rate_limit
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::HashMap, 
+    net::IpAddr, 
+    sync::{Arc, Mutex}, 
+    time::{Duration, Instant}
+};
+
+use axum::{
+    extract::{Request, State}, 
+    http::{header, StatusCode}, 
+    middleware::Next, 
+    response::{IntoResponse, Response}
+};
+
+use crate::{server::client_ip::ClientIpConfig, util::error::ApiError};
+
+/// 登录限流的配置, 见 [`Config::rate_limit`](crate::config::Config::rate_limit)
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// 每个窗口内允许的请求数
+    pub requests: u32,
+    /// 窗口长度
+    pub window: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self { 
+            requests: 10, 
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// 按客户端 IP 计数的固定窗口限流器
+/// 
+/// 无法确定地址的请求(如未以 `into_make_service_with_connect_info` 启动)共用一个窗口, 
+/// 而不是不受限制
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    client_ip: Arc<ClientIpConfig>,
+    windows: Mutex<HashMap<Option<IpAddr>, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, client_ip: Arc<ClientIpConfig>) -> Self {
+        Self { config, client_ip, windows: Mutex::new(HashMap::new()) }
+    }
+
+    /// 记录一次来自 `ip` 的请求, 超出限额时返回距离窗口重置的时间; 
+    /// `None` 表示地址未知的请求
+    pub fn check(&self, ip: Option<IpAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().expect("rate limiter lock poisoned");
+        // 顺带清理已过期的窗口, 避免表无限增长
+        windows.retain(|_, (start, _)| now.duration_since(*start) < self.config.window);
+        let (start, count) = windows.entry(ip).or_insert((now, 0));
+        if *count >= self.config.requests {
+            return Err(self.config.window.saturating_sub(now.duration_since(*start)));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// 限流中间件, 超出限额时返回 `429 Too Many Requests` 及 `Retry-After`; 
+/// 来自可信网段的请求不计数
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>, request: Request, next: Next
+) -> Response {
+    let ip = limiter.client_ip.client_ip(request.headers(), request.extensions());
+    if ip.is_some_and(|ip| limiter.client_ip.is_trusted(ip)) {
+        return next.run(request).await;
+    }
+    match limiter.check(ip) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => (
+            [(header::RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+            ApiError::new(StatusCode::TOO_MANY_REQUESTS, "too many requests"),
+        ).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests: u32) -> RateLimiter {
+        let config = RateLimitConfig { requests, window: Duration::from_secs(60) };
+        RateLimiter::new(config, Arc::new(ClientIpConfig::default()))
+    }
+
+    #[test]
+    fn requests_over_limit_are_rejected_per_ip() {
+        let limiter = limiter(2);
+        let a = Some("10.0.0.1".parse().unwrap());
+        let b = Some("10.0.0.2".parse().unwrap());
+        assert!(limiter.check(a).is_ok());
+        assert!(limiter.check(a).is_ok());
+        assert!(limiter.check(a).is_err());
+        assert!(limiter.check(b).is_ok());
+    }
+
+    #[test]
+    fn unknown_clients_share_one_window() {
+        let limiter = limiter(2);
+        assert!(limiter.check(None).is_ok());
+        assert!(limiter.check(None).is_ok());
+        let retry_after = limiter.check(None).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,50 @@
+/*
+*   server::security_headers
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use axum::http::{header, HeaderValue};
+use tower::layer::util::Stack;
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// 默认的 `Content-Security-Policy`; Swagger UI 需要同源脚本、内联样式与 data: 图片
+pub const DEFAULT_CSP: &str = 
+    "default-src 'self'; img-src 'self' data:; style-src 'self' 'unsafe-inline'; frame-ancestors 'none'";
+
+pub type SecurityHeadersLayer = Stack<
+    SetResponseHeaderLayer<HeaderValue>, 
+    Stack<SetResponseHeaderLayer<HeaderValue>, SetResponseHeaderLayer<HeaderValue>>
+>;
+
+/// 为所有响应加上 `X-Content-Type-Options: nosniff`、`X-Frame-Options: DENY` 
+/// 与给定的 `Content-Security-Policy`; 处理器已设置的同名响应头保持不变
+pub fn security_headers_layer(csp: &str) -> anyhow::Result<SecurityHeadersLayer> {
+    let csp = HeaderValue::from_str(csp)
+        .map_err(|err| anyhow::anyhow!("invalid Content-Security-Policy `{csp}`: {err}"))?;
+    Ok(Stack::new(
+        SetResponseHeaderLayer::if_not_present(header::CONTENT_SECURITY_POLICY, csp),
+        Stack::new(
+            SetResponseHeaderLayer::if_not_present(
+                header::X_CONTENT_TYPE_OPTIONS, 
+                HeaderValue::from_static("nosniff")
+            ),
+            SetResponseHeaderLayer::if_not_present(
+                header::X_FRAME_OPTIONS, 
+                HeaderValue::from_static("DENY")
+            ),
+        ),
+    ))
+}
@@ -0,0 +1,77 @@
+/*
+*   server::state
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+
+use crate::{
+    database::Pool, 
+    model::{repository::UserRepository, user::UserConfig}, 
+    server::{auth::AuthConfig, client_ip::ClientIpConfig}, 
+    util::keys::Keys
+};
+
+/// 所有路由共享的状态, 处理函数通过 [`FromRef`] 只提取自己需要的部分, 
+/// 如 `State<Pool>`
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: Pool,
+    /// 用户存储, 通常就是 `pool`; 测试时可以替换为其他实现
+    pub users: Arc<dyn UserRepository>,
+    pub keys: Arc<Keys>,
+    pub auth_config: AuthConfig,
+    pub client_ip: Arc<ClientIpConfig>,
+    pub user_config: UserConfig,
+}
+
+impl FromRef<AppState> for Pool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn UserRepository> {
+    fn from_ref(state: &AppState) -> Self {
+        state.users.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Keys> {
+    fn from_ref(state: &AppState) -> Self {
+        state.keys.clone()
+    }
+}
+
+impl FromRef<AppState> for AuthConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.auth_config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<ClientIpConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.client_ip.clone()
+    }
+}
+
+impl FromRef<AppState> for UserConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.user_config.clone()
+    }
+}
@@ -16,13 +16,99 @@
 *   along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use axum::http::StatusCode;
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// 统一的接口错误, 响应体为 `{ "error": ..., "code": ... }`, 
+/// 与 `AuthError` 的格式一致
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    /// 出错的请求字段
+    pub field: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status, message: message.into(), field: None }
+    }
+
+    pub fn with_field(mut self, field: Option<String>) -> Self {
+        self.field = field;
+        self
+    }
+}
+
+/// 错误响应体
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    pub code: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        let body = Json(ErrorBody {
+            error: self.message,
+            code: self.status.as_u16(),
+            field: self.field,
+        });
+        (self.status, body).into_response()
+    }
+}
 
 /// Utility function for mapping any error into a `500 Internal Server Error`
 /// response.
-pub fn internal_error<E>(err: E) -> (StatusCode, String)
+/// 
+/// 错误详情(可能包含 SQL、表名或文件路径)只记录在日志中, 不返回给客户端
+pub fn internal_error<E>(err: E) -> ApiError
 where
     E: std::error::Error,
 {
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
\ No newline at end of file
+    tracing::error!(%err, "internal error");
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+/// 是否为连接层面的错误(连接断开、连接池超时或已关闭), 
+/// 此时语句可能根本没有送达数据库, 也可能已经执行但结果丢失
+pub fn is_connection_error(err: &sqlx::Error) -> bool {
+    matches!(
+        err, 
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed
+    )
+}
+
+/// 将数据库错误映射为响应: 连接错误返回 `503 Service Unavailable`, 其余返回 `500`
+/// 
+/// 写操作遇到连接错误时不会自动重试, 以免重复写入; 只有只读查询会经由
+/// [`retry_read`](crate::model::repository::retry_read) 重试一次
+pub fn database_error(err: sqlx::Error) -> ApiError {
+    if is_connection_error(&err) {
+        tracing::warn!(%err, "database unavailable");
+        return ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "database unavailable, try again later");
+    }
+    internal_error(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_error_hides_details() {
+        let err = internal_error(sqlx::Error::Protocol("Table 'apb.user' doesn't exist".to_string()));
+        assert_eq!(err.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.message, "internal server error");
+    }
+
+    #[test]
+    fn connection_errors_are_unavailable() {
+        let err = database_error(sqlx::Error::PoolTimedOut);
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!err.message.contains("PoolTimedOut"));
+    }
+}
@@ -0,0 +1,141 @@
+/*
+*   util::json
+*   Copyright (C) 2025 zlc
+*
+*   This program is free software: you can redistribute it and/or modify
+*   it under the terms of the GNU General Public License as published by
+*   the Free Software Foundation, either version 3 of the License, or
+*   (at your option) any later version.
+*
+*   This program is distributed in the hope that it will be useful,
+*   but WITHOUT ANY WARRANTY; without even the implied warranty of
+*   MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+*   GNU General Public License for more details.
+*
+*   You should have received a copy of the GNU General Public License
+*   along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use axum::{
+    extract::{rejection::{JsonDataError, JsonRejection}, FromRequest, Request}, 
+    http::StatusCode, 
+    Json
+};
+use serde::de::DeserializeOwned;
+
+use crate::util::error::ApiError;
+
+/// 与 [`axum::Json`] 相同, 但请求体无法解析时返回 [`ApiError`] 格式的错误, 
+/// 并尽可能指出出错的字段
+#[derive(Debug)]
+pub struct ApiJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ApiJson<T>
+where 
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(req, state).await {
+            Ok(Json(value)) => Ok(Self(value)),
+            Err(JsonRejection::JsonDataError(err)) => {
+                let (field, message) = data_error_detail(&err);
+                Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, message).with_field(field))
+            }
+            // 缺少或错误的 Content-Type 统一说明期望的类型, 不区分具体原因
+            Err(JsonRejection::MissingJsonContentType(_)) => Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE, 
+                "expected request with `Content-Type: application/json`"
+            )),
+            Err(rejection) => Err(ApiError::new(rejection.status(), rejection.body_text())),
+        }
+    }
+}
+
+// axum 内部使用 serde_path_to_error 反序列化, 从错误链中取出字段路径
+fn data_error_detail(err: &JsonDataError) -> (Option<String>, String) {
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<serde_path_to_error::Error<serde_json::Error>>() {
+            let message = err.inner().to_string();
+            let field = match err.path().to_string() {
+                // 缺少字段时路径为根, 字段名只出现在错误信息中
+                path if path == "." => missing_field(&message),
+                path => Some(path),
+            };
+            return (field, message);
+        }
+        source = err.source();
+    }
+    (None, err.body_text())
+}
+
+fn missing_field(message: &str) -> Option<String> {
+    message
+        .strip_prefix("missing field `")?
+        .split('`')
+        .next()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header::CONTENT_TYPE, response::IntoResponse};
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Payload {
+        name: String,
+        age: u32,
+    }
+
+    /// 以 `body` 为请求体解析 [`Payload`], 返回错误响应的状态码与响应体
+    async fn reject(body: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let err = ApiJson::<Payload>::from_request(request, &()).await.unwrap_err();
+        let response = err.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn valid_body_is_parsed() {
+        let request = Request::post("/")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{ "name": "alice", "age": 30 }"#))
+            .unwrap();
+        let ApiJson(payload) = ApiJson::<Payload>::from_request(request, &()).await.unwrap();
+        assert_eq!((payload.name.as_str(), payload.age), ("alice", 30));
+    }
+
+    #[tokio::test]
+    async fn missing_field_is_reported() {
+        let (status, body) = reject(r#"{ "age": 30 }"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], 422);
+        assert_eq!(body["field"], "name");
+    }
+
+    #[tokio::test]
+    async fn wrong_type_is_reported() {
+        let (status, body) = reject(r#"{ "name": "alice", "age": "thirty" }"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["field"], "age");
+        assert!(body["error"].as_str().unwrap().contains("invalid type"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_bad_request() {
+        let (status, body) = reject(r#"{ "name": "alice", "#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.get("field").is_none(), "{body}");
+    }
+}
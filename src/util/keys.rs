@@ -16,11 +16,18 @@
 *   along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::ops::Deref;
+use std::{collections::HashMap, ops::Deref};
+
+use sha2::{Digest, Sha256};
 
 pub trait AuthKeys {
     fn get_encoding(&self) -> &jsonwebtoken::EncodingKey;
     fn get_decoding(&self) -> &jsonwebtoken::DecodingKey;
+    fn get_algorithm(&self) -> jsonwebtoken::Algorithm;
+    /// 当前签名密钥的标识, 签发令牌时写入头部的 `kid`
+    fn get_kid(&self) -> &str;
+    /// 按令牌头部的 `kid` 选择验证密钥, 没有 `kid` 的令牌使用当前密钥
+    fn get_decoding_by_kid(&self, kid: Option<&str>) -> Option<&jsonwebtoken::DecodingKey>;
 }
 
 impl<T> AuthKeys for T
@@ -35,19 +42,66 @@ where
     fn get_decoding(&self) -> &jsonwebtoken::DecodingKey {
         self.deref().get_decoding()
     }
+
+    fn get_algorithm(&self) -> jsonwebtoken::Algorithm {
+        self.deref().get_algorithm()
+    }
+
+    fn get_kid(&self) -> &str {
+        self.deref().get_kid()
+    }
+
+    fn get_decoding_by_kid(&self, kid: Option<&str>) -> Option<&jsonwebtoken::DecodingKey> {
+        self.deref().get_decoding_by_kid(kid)
+    }
 }
 
+/// 签名密钥集合: 使用当前密钥签名, 验证时还接受轮换前的旧密钥, 
+/// 使更换密钥时已签发的令牌在过期前仍然有效
 pub struct Keys {
     encoding: jsonwebtoken::EncodingKey,
     decoding: jsonwebtoken::DecodingKey,
+    algorithm: jsonwebtoken::Algorithm,
+    kid: String,
+    /// 旧密钥的 `kid` 到验证密钥的映射
+    previous: HashMap<String, jsonwebtoken::DecodingKey>,
+}
+
+/// 由密钥材料派生 `kid`, 同一密钥在各实例上得到相同的标识
+fn derive_kid(material: &[u8]) -> String {
+    Sha256::digest(material)[..8].iter().map(|byte| format!("{byte:02x}")).collect()
 }
 
 impl Keys {
+    /// 使用对称密钥, 以 HS256 签名
     pub fn new(secret: &[u8]) -> Self {
         Self {
             encoding: jsonwebtoken::EncodingKey::from_secret(secret),
             decoding: jsonwebtoken::DecodingKey::from_secret(secret),
+            algorithm: jsonwebtoken::Algorithm::HS256,
+            kid: derive_kid(secret),
+            previous: HashMap::new(),
+        }
+    }
+
+    /// 加入轮换前的对称密钥, 仅用于验证
+    pub fn with_previous_secrets<S: AsRef<[u8]>>(mut self, secrets: &[S]) -> Self {
+        for secret in secrets {
+            let secret = secret.as_ref();
+            self.previous.insert(derive_kid(secret), jsonwebtoken::DecodingKey::from_secret(secret));
         }
+        self
+    }
+
+    /// 使用 PEM 格式的 RSA 私钥签名、公钥验证, 以 RS256 签名
+    pub fn from_rsa_pem(private_pem: &[u8], public_pem: &[u8]) -> Result<Self, jsonwebtoken::errors::Error> {
+        Ok(Self {
+            encoding: jsonwebtoken::EncodingKey::from_rsa_pem(private_pem)?,
+            decoding: jsonwebtoken::DecodingKey::from_rsa_pem(public_pem)?,
+            algorithm: jsonwebtoken::Algorithm::RS256,
+            kid: derive_kid(public_pem),
+            previous: HashMap::new(),
+        })
     }
 }
 
@@ -59,4 +113,43 @@ impl AuthKeys for Keys {
     fn get_decoding(&self) -> &jsonwebtoken::DecodingKey {
         &self.decoding
     }
+
+    fn get_algorithm(&self) -> jsonwebtoken::Algorithm {
+        self.algorithm
+    }
+
+    fn get_kid(&self) -> &str {
+        &self.kid
+    }
+
+    fn get_decoding_by_kid(&self, kid: Option<&str>) -> Option<&jsonwebtoken::DecodingKey> {
+        match kid {
+            None => Some(&self.decoding),
+            Some(kid) if kid == self.kid => Some(&self.decoding),
+            Some(kid) => self.previous.get(kid),
+        }
+    }
+}
+
+/// 读取签名密钥: 优先使用 `secret`, 其次读取 `secret_file` 指向的文件
+/// 
+/// 两者均未设置时, 调试构建退回到不安全的开发用密钥, 发布构建直接报错
+pub fn load_secret(secret: Option<&str>, secret_file: Option<&str>) -> anyhow::Result<Vec<u8>> {
+    let secret = if let Some(secret) = secret {
+        secret.as_bytes().to_vec()
+    } else if let Some(path) = secret_file {
+        std::fs::read(path)
+            .map_err(|err| anyhow::anyhow!("failed to read JWT secret file `{path}`: {err}"))?
+            .trim_ascii_end()
+            .to_vec()
+    } else if cfg!(debug_assertions) {
+        tracing::warn!("JWT secret is not configured, using an insecure development secret");
+        b"Free as in Freedom".to_vec()
+    } else {
+        anyhow::bail!("JWT secret is not configured, set jwt.secret or jwt.secret_file (APB_JWT_SECRET, APB_JWT_SECRET_FILE)");
+    };
+    if secret.is_empty() {
+        anyhow::bail!("JWT secret must not be empty");
+    }
+    Ok(secret)
 }
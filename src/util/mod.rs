@@ -1,3 +1,4 @@
 pub mod error;
+pub mod json;
 pub mod password;
 pub mod keys;
\ No newline at end of file
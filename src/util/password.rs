@@ -16,33 +16,366 @@
 *   along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::marker::PhantomData;
+use std::{collections::HashMap, fmt::Display, marker::PhantomData, str::FromStr, sync::OnceLock};
 
+use hmac::{Hmac, Mac};
 use serde::{de::Visitor, Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
 
-pub trait PasswordProperties {}
+pub trait PasswordProperties {
+    type Hasher: PasswordHasher;
+    /// 口令的最小长度(字符数)
+    const MIN_LEN: usize = 8;
+    /// 口令至少需要包含的字符类别数(小写字母、大写字母、数字、其他字符)
+    const MIN_CHAR_CLASSES: usize = 2;
+}
+
+/// 口令散列算法
+pub trait PasswordHasher {
+    /// 算法能够完整使用的口令字节数, 更长的部分会被忽略
+    const MAX_INPUT_BYTES: usize = usize::MAX;
+
+    fn hash(password: &str, cost: u32, salt: [u8; 16]) -> Result<String, PasswordError>;
+
+    /// `hash` 是否由本算法以不低于 `cost` 的代价生成, 否则校验成功后应重新散列
+    fn is_current(hash: &str, cost: u32) -> bool;
+}
+
+/// 可在配置中选择的散列算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Bcrypt,
+    /// Argon2id
+    Argon2,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bcrypt" => Ok(Self::Bcrypt),
+            "argon2" => Ok(Self::Argon2),
+            _ => Err("expected bcrypt or argon2".to_string()),
+        }
+    }
+}
+
+pub mod hasher {
+    use argon2::PasswordHasher as _;
+
+    use super::PasswordError;
+
+    #[derive(Debug)]
+    pub struct Bcrypt;
+
+    /// Argon2id, `cost` 作为迭代次数
+    #[derive(Debug)]
+    pub struct Argon2;
+
+    impl super::PasswordHasher for Bcrypt {
+        /// bcrypt 只使用前 72 字节, 超出部分不同的口令会得到相同的散列
+        const MAX_INPUT_BYTES: usize = 72;
+
+        fn hash(password: &str, cost: u32, salt: [u8; 16]) -> Result<String, PasswordError> {
+            bcrypt::hash_with_salt(password, cost, salt)
+                .map(|parts| parts.to_string())
+                .map_err(PasswordError::Bcrypt)
+        }
+
+        fn is_current(hash: &str, cost: u32) -> bool {
+            hash.parse::<bcrypt::HashParts>().is_ok_and(|parts| parts.get_cost() >= cost)
+        }
+    }
+
+    impl super::PasswordHasher for Argon2 {
+        fn hash(password: &str, cost: u32, salt: [u8; 16]) -> Result<String, PasswordError> {
+            let salt = argon2::password_hash::SaltString::encode_b64(&salt)
+                .map_err(PasswordError::Argon2)?;
+            let params = argon2::Params::new(
+                argon2::Params::DEFAULT_M_COST, cost, argon2::Params::DEFAULT_P_COST, None
+            ).map_err(|err| PasswordError::Argon2(err.into()))?;
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(PasswordError::Argon2)
+        }
+
+        fn is_current(hash: &str, cost: u32) -> bool {
+            let Ok(hash) = argon2::PasswordHash::new(hash) else {
+                return false;
+            };
+            hash.algorithm == argon2::Algorithm::Argon2id.ident()
+                && argon2::Params::try_from(&hash).is_ok_and(|params| {
+                    params.t_cost() >= cost && params.m_cost() >= argon2::Params::DEFAULT_M_COST
+                })
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PasswordError {
+    Bcrypt(bcrypt::BcryptError),
+    Argon2(argon2::password_hash::Error),
+    Rand(getrandom::Error),
+    /// 散列使用的 pepper 版本未配置
+    UnknownPepper(u32),
+}
+
+impl Display for PasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordError::Bcrypt(err) => write!(f, "bcrypt: {err}"),
+            PasswordError::Argon2(err) => write!(f, "argon2: {err}"),
+            PasswordError::Rand(err) => write!(f, "random: {err}"),
+            PasswordError::UnknownPepper(version) => write!(f, "pepper version {version} is not configured"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordError {}
+
+/// 口令不满足 [`PasswordProperties`] 规定的强度要求
+#[derive(Debug)]
+pub enum WeakPassword {
+    TooShort { min: usize },
+    /// 超出散列算法能使用的长度, 按 UTF-8 字节计
+    TooLong { max_bytes: usize },
+    TooFewCharClasses { min: usize },
+}
+
+impl Display for WeakPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WeakPassword::TooShort { min } => {
+                write!(f, "password must be at least {min} characters long")
+            }
+            WeakPassword::TooLong { max_bytes } => {
+                write!(f, "password must be at most {max_bytes} bytes long")
+            }
+            WeakPassword::TooFewCharClasses { min } => write!(
+                f, 
+                "password must contain at least {min} of: lowercase letters, uppercase letters, digits, other characters"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WeakPassword {}
+
+/// 服务端密钥(pepper), 在散列前以 HMAC-SHA256 混入口令, 仅泄露数据库时无法离线猜测口令
+/// 
+/// 使用 pepper 生成的散列带有 `$pv<版本>$` 前缀, 校验时按版本选择 pepper, 
+/// 因此轮换 pepper 时只需保留旧版本, 用户下次登录时会以当前 pepper 重新散列
+/// 
+/// 没有前缀的散列生成于启用 pepper 或引入版本之前, 默认按无 pepper 校验; 
+/// 若之前使用过不带版本的 pepper, 需通过 [`Peppers::with_legacy`] 显式配置
+pub struct Peppers {
+    current: u32,
+    keys: HashMap<u32, Vec<u8>>,
+    /// 校验没有版本前缀的散列时使用的 pepper
+    legacy: Option<Vec<u8>>,
+}
+
+/// 未指定版本时当前 pepper 的版本
+pub const DEFAULT_PEPPER_VERSION: u32 = 1;
+
+static PEPPERS: OnceLock<Peppers> = OnceLock::new();
+
+impl Peppers {
+    /// 当前 pepper 及其版本, 新的散列都使用它
+    pub fn new(version: u32, pepper: Vec<u8>) -> anyhow::Result<Self> {
+        let mut peppers = Self { current: version, keys: HashMap::new(), legacy: None };
+        peppers.insert(version, pepper)?;
+        Ok(peppers)
+    }
+
+    /// 加入轮换前的 pepper, 仅用于校验
+    pub fn with_previous(mut self, version: u32, pepper: Vec<u8>) -> anyhow::Result<Self> {
+        self.insert(version, pepper)?;
+        Ok(self)
+    }
+
+    /// 引入版本前使用的 pepper, 仅用于校验没有版本前缀的散列
+    pub fn with_legacy(mut self, pepper: Vec<u8>) -> anyhow::Result<Self> {
+        if pepper.is_empty() {
+            anyhow::bail!("legacy password pepper must not be empty");
+        }
+        self.legacy = Some(pepper);
+        Ok(self)
+    }
+
+    fn current_key(&self) -> &[u8] {
+        &self.keys[&self.current]
+    }
+
+    fn insert(&mut self, version: u32, pepper: Vec<u8>) -> anyhow::Result<()> {
+        if pepper.is_empty() {
+            anyhow::bail!("password pepper version {version} must not be empty");
+        }
+        if self.keys.insert(version, pepper).is_some() {
+            anyhow::bail!("password pepper version {version} is configured more than once");
+        }
+        Ok(())
+    }
+}
+
+/// 设置 pepper, 只能在启动时设置一次; 未设置时口令原样交给散列算法
+pub fn set_pepper(peppers: Peppers) -> anyhow::Result<()> {
+    PEPPERS.set(peppers).map_err(|_| anyhow::anyhow!("password pepper is already set"))
+}
+
+/// 拆分出散列的 pepper 版本, 没有前缀时返回 `None`
+fn split_pepper_version(hash: &str) -> (Option<u32>, &str) {
+    hash.strip_prefix("$pv")
+        .and_then(|rest| rest.split_once('$'))
+        .and_then(|(version, inner)| Some((Some(version.parse().ok()?), inner)))
+        .unwrap_or((None, hash))
+}
+
+/// 返回实际交给散列算法的口令; HMAC 结果以十六进制表示, 不超过 bcrypt 的 72 字节上限
+fn apply_pepper(password: &str, pepper: Option<&[u8]>) -> Zeroizing<String> {
+    match pepper {
+        Some(pepper) => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(pepper)
+                .expect("HMAC accepts keys of any length");
+            mac.update(password.as_bytes());
+            Zeroizing::new(
+                mac.finalize().into_bytes().iter().map(|byte| format!("{byte:02x}")).collect()
+            )
+        }
+        None => Zeroizing::new(password.to_string()),
+    }
+}
+
+/// 以当前 pepper 散列口令, 并在结果前加上版本前缀
+fn hash_peppered<H: PasswordHasher>(password: &str, cost: u32, salt: [u8; 16]) -> Result<String, PasswordError> {
+    hash_with_peppers::<H>(PEPPERS.get(), password, cost, salt)
+}
+
+fn hash_with_peppers<H: PasswordHasher>(
+    peppers: Option<&Peppers>, password: &str, cost: u32, salt: [u8; 16]
+) -> Result<String, PasswordError> {
+    match peppers {
+        Some(peppers) => {
+            let hash = H::hash(&apply_pepper(password, Some(peppers.current_key())), cost, salt)?;
+            Ok(format!("$pv{}${hash}", peppers.current))
+        }
+        None => H::hash(password, cost, salt),
+    }
+}
+
+/// 校验成功后应重新散列的情况: 
+/// `hash` 不是由 `P` 当前的算法生成, 或代价低于 [`PasswordWithRandomSalt::cost`], 
+/// 或者未使用当前版本的 pepper(包括启用 pepper 前生成的没有前缀的散列)
+pub fn needs_rehash<P: PasswordWithRandomSalt>(hash: &str) -> bool {
+    rehash_needed::<P::Hasher>(PEPPERS.get(), hash, P::cost())
+}
+
+fn rehash_needed<H: PasswordHasher>(peppers: Option<&Peppers>, hash: &str, cost: u32) -> bool {
+    let (version, hash) = split_pepper_version(hash);
+    let stale_pepper = peppers.is_some_and(|peppers| version != Some(peppers.current));
+    stale_pepper || !H::is_current(hash, cost)
+}
+
+/// 校验口令, 根据 `hash` 的前缀选择 pepper 并判断其由哪种算法生成
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    verify_with_peppers(PEPPERS.get(), password, hash)
+}
+
+fn verify_with_peppers(peppers: Option<&Peppers>, password: &str, hash: &str) -> Result<bool, PasswordError> {
+    use argon2::PasswordVerifier as _;
+
+    let (version, hash) = split_pepper_version(hash);
+    let pepper = match (version, peppers) {
+        (Some(version), Some(peppers)) => {
+            Some(peppers.keys.get(&version).ok_or(PasswordError::UnknownPepper(version))?.as_slice())
+        }
+        (Some(version), None) => return Err(PasswordError::UnknownPepper(version)),
+        (None, peppers) => peppers.and_then(|peppers| peppers.legacy.as_deref()),
+    };
+    let password = apply_pepper(password, pepper);
+    if hash.starts_with("$argon2") {
+        let hash = argon2::PasswordHash::new(hash).map_err(PasswordError::Argon2)?;
+        match argon2::Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(err) => Err(PasswordError::Argon2(err)),
+        }
+    } else {
+        bcrypt::verify(password.as_bytes(), hash).map_err(PasswordError::Bcrypt)
+    }
+}
 
 pub trait PasswordWithSalt: PasswordProperties {
     const COST: u32;
     const SALT: [u8; 16];
+
+    /// 实际使用的代价, 默认为 `COST`, 可在运行时覆盖
+    fn cost() -> u32 {
+        Self::COST
+    }
 }
 
 pub trait PasswordWithRandomSalt: PasswordProperties {
     const COST: u32;
+
+    /// 实际使用的代价, 默认为 `COST`, 可在运行时覆盖
+    fn cost() -> u32 {
+        Self::COST
+    }
 }
 
-#[derive(Debug, Clone)]
+/// 明文口令, 释放时会将内存清零, `Debug` 输出不包含明文
+#[derive(Clone)]
 pub struct StringPassword<P: PasswordProperties> {
     pub value: String,
     _mark: PhantomData<P>,
 }
 
+impl<P: PasswordProperties> Drop for StringPassword<P> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl<P: PasswordProperties> std::fmt::Debug for StringPassword<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StringPassword(***)")
+    }
+}
+
 impl<P: PasswordProperties> StringPassword<P> {
     pub fn new(value: String) -> Self {
         Self { value, _mark: PhantomData }
     }
 }
 
+impl<P: PasswordProperties> StringPassword<P> {
+    pub fn check_strength(&self) -> Result<(), WeakPassword> {
+        if self.value.chars().count() < P::MIN_LEN {
+            return Err(WeakPassword::TooShort { min: P::MIN_LEN });
+        }
+        // 即使启用了 pepper(散列的输入长度固定)也拒绝, 使口令规则不随部署配置变化
+        if self.value.len() > P::Hasher::MAX_INPUT_BYTES {
+            return Err(WeakPassword::TooLong { max_bytes: P::Hasher::MAX_INPUT_BYTES });
+        }
+        let classes = [
+            self.value.chars().any(|c| c.is_lowercase()),
+            self.value.chars().any(|c| c.is_uppercase()),
+            self.value.chars().any(|c| c.is_numeric()),
+            self.value.chars().any(|c| !c.is_alphanumeric()),
+        ];
+        if classes.iter().filter(|&&present| present).count() < P::MIN_CHAR_CLASSES {
+            return Err(WeakPassword::TooFewCharClasses { min: P::MIN_CHAR_CLASSES });
+        }
+        Ok(())
+    }
+}
+
 impl<P: PasswordProperties> Serialize for StringPassword<P> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -80,20 +413,180 @@ impl<'de, P: PasswordProperties> Deserialize<'de> for StringPassword<P> {
 }
 
 impl<P: PasswordWithSalt> StringPassword<P> {
-    pub fn hash_with_salt(&self) -> Result<String, bcrypt::BcryptError> {
-        bcrypt::hash_with_salt(&self.value, P::COST, P::SALT).map(|parts| {
-            parts.to_string()
-        })
+    pub fn hash_with_salt(&self) -> Result<String, PasswordError> {
+        hash_peppered::<P::Hasher>(&self.value, P::cost(), P::SALT)
     }
 }
 
 impl<P: PasswordWithRandomSalt> StringPassword<P> {
-    pub fn hash_with_random_salt(&self) -> Result<String, bcrypt::BcryptError> {
+    pub fn hash_with_random_salt(&self) -> Result<String, PasswordError> {
         let mut salt = [0u8; 16];
-        getrandom::fill(&mut salt).map_err(|err| bcrypt::BcryptError::Rand(err))?;
-        bcrypt::hash_with_salt(&self.value, P::COST, salt).map(|parts| {
-            parts.to_string()
-        })
+        getrandom::fill(&mut salt).map_err(PasswordError::Rand)?;
+        hash_peppered::<P::Hasher>(&self.value, P::cost(), salt)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试使用 bcrypt 允许的最低代价
+    const COST: u32 = 4;
+    const SALT: [u8; 16] = [7; 16];
+
+    /// 使用默认强度要求的口令
+    struct TestProperties;
+
+    impl PasswordProperties for TestProperties {
+        type Hasher = hasher::Bcrypt;
+    }
+
+    fn strength(password: &str) -> Result<(), WeakPassword> {
+        StringPassword::<TestProperties>::new(password.to_string()).check_strength()
+    }
+
+    #[test]
+    fn apply_pepper_without_pepper_is_identity() {
+        assert_eq!(*apply_pepper("secret", None), "secret");
+    }
+
+    #[test]
+    fn apply_pepper_is_keyed_hex_hmac() {
+        let peppered = apply_pepper("secret", Some(b"pepper"));
+        assert_eq!(peppered.len(), 64);
+        assert!(peppered.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(*peppered, *apply_pepper("secret", Some(b"pepper")));
+        assert_ne!(*peppered, *apply_pepper("secret", Some(b"other pepper")));
+    }
+
+    #[test]
+    fn peppered_hash_round_trips() {
+        let peppers = Peppers::new(2, b"pepper".to_vec()).unwrap();
+        let hash = hash_with_peppers::<hasher::Bcrypt>(Some(&peppers), "secret", COST, SALT).unwrap();
+        assert!(hash.starts_with("$pv2$$2"));
+        assert!(verify_with_peppers(Some(&peppers), "secret", &hash).unwrap());
+        assert!(!verify_with_peppers(Some(&peppers), "wrong", &hash).unwrap());
+    }
+
+    #[test]
+    fn peppered_hash_does_not_verify_with_another_pepper() {
+        let peppers = Peppers::new(2, b"pepper".to_vec()).unwrap();
+        let hash = hash_with_peppers::<hasher::Bcrypt>(Some(&peppers), "secret", COST, SALT).unwrap();
+        let other = Peppers::new(2, b"other pepper".to_vec()).unwrap();
+        assert!(!verify_with_peppers(Some(&other), "secret", &hash).unwrap());
+        assert!(matches!(verify_with_peppers(None, "secret", &hash), Err(PasswordError::UnknownPepper(2))));
+    }
+
+    #[test]
+    fn previous_pepper_still_verifies() {
+        let old = Peppers::new(2, b"old pepper".to_vec()).unwrap();
+        let hash = hash_with_peppers::<hasher::Bcrypt>(Some(&old), "secret", COST, SALT).unwrap();
+        let rotated = Peppers::new(3, b"new pepper".to_vec()).unwrap()
+            .with_previous(2, b"old pepper".to_vec()).unwrap();
+        assert!(verify_with_peppers(Some(&rotated), "secret", &hash).unwrap());
+    }
+
+    #[test]
+    fn empty_or_duplicate_pepper_is_rejected() {
+        assert!(Peppers::new(1, Vec::new()).is_err());
+        let peppers = Peppers::new(1, b"pepper".to_vec()).unwrap();
+        assert!(peppers.with_previous(1, b"again".to_vec()).is_err());
+    }
+
+    #[test]
+    fn split_pepper_version_parses_prefix() {
+        assert_eq!(split_pepper_version("$pv3$$2b$04$abc"), (Some(3), "$2b$04$abc"));
+        assert_eq!(split_pepper_version("$2b$04$abc"), (None, "$2b$04$abc"));
+        assert_eq!(split_pepper_version("$argon2id$v=19$abc"), (None, "$argon2id$v=19$abc"));
+        // 版本不是数字时不当作前缀
+        assert_eq!(split_pepper_version("$pvx$$2b$04$abc"), (None, "$pvx$$2b$04$abc"));
+    }
+
+    #[test]
+    fn unprefixed_hash_verifies_without_pepper_after_enabling_one() {
+        let hash = hash_with_peppers::<hasher::Bcrypt>(None, "secret", COST, SALT).unwrap();
+        let peppers = Peppers::new(DEFAULT_PEPPER_VERSION, b"pepper".to_vec()).unwrap();
+        assert!(verify_with_peppers(Some(&peppers), "secret", &hash).unwrap());
+        assert!(rehash_needed::<hasher::Bcrypt>(Some(&peppers), &hash, COST));
+    }
+
+    #[test]
+    fn unprefixed_hash_uses_explicit_legacy_pepper() {
+        let legacy = apply_pepper("secret", Some(b"legacy"));
+        let hash = hasher::Bcrypt::hash(&legacy, COST, SALT).unwrap();
+        let peppers = Peppers::new(2, b"pepper".to_vec()).unwrap()
+            .with_legacy(b"legacy".to_vec()).unwrap();
+        assert!(verify_with_peppers(Some(&peppers), "secret", &hash).unwrap());
+        assert!(!verify_with_peppers(Some(&peppers.with_legacy(b"other".to_vec()).unwrap()), "secret", &hash).unwrap());
+    }
+
+    #[test]
+    fn current_hash_needs_no_rehash() {
+        let peppers = Peppers::new(2, b"pepper".to_vec()).unwrap();
+        let hash = hash_with_peppers::<hasher::Bcrypt>(Some(&peppers), "secret", COST, SALT).unwrap();
+        assert!(!rehash_needed::<hasher::Bcrypt>(Some(&peppers), &hash, COST));
+        assert!(rehash_needed::<hasher::Bcrypt>(Some(&peppers), &hash, COST + 1));
+        let rotated = Peppers::new(3, b"new".to_vec()).unwrap();
+        assert!(rehash_needed::<hasher::Bcrypt>(Some(&rotated), &hash, COST));
+    }
+
+    #[test]
+    fn strong_password_is_accepted() {
+        assert!(strength("abcdefg1").is_ok());
+        assert!(strength("Passwort").is_ok());
+        assert!(strength("口令口令口令口令1!").is_ok());
+        assert!(strength(&"a1".repeat(36)).is_ok());
+    }
+
+    #[test]
+    fn short_password_is_rejected() {
+        assert!(matches!(strength("abcde1!"), Err(WeakPassword::TooShort { min: 8 })));
+        // 按字符而不是字节计数
+        assert!(matches!(strength("口令口令口令1"), Err(WeakPassword::TooShort { .. })));
+    }
+
+    #[test]
+    fn password_longer_than_hasher_input_is_rejected() {
+        assert!(matches!(strength(&"a1".repeat(37)), Err(WeakPassword::TooLong { max_bytes: 72 })));
+    }
+
+    #[test]
+    fn single_char_class_password_is_rejected() {
+        for password in ["abcdefgh", "ABCDEFGH", "12345678", "!@#$%^&*"] {
+            assert!(matches!(strength(password), Err(WeakPassword::TooFewCharClasses { min: 2 })), "{password}");
+        }
+    }
+
+    #[test]
+    fn argon2_hash_round_trips() {
+        let hash = hash_with_peppers::<hasher::Argon2>(None, "secret", 2, SALT).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_with_peppers(None, "secret", &hash).unwrap());
+        assert!(!verify_with_peppers(None, "wrong", &hash).unwrap());
+    }
+
+    #[test]
+    fn switching_algorithm_needs_rehash() {
+        let bcrypt = hash_with_peppers::<hasher::Bcrypt>(None, "secret", COST, SALT).unwrap();
+        let argon2 = hash_with_peppers::<hasher::Argon2>(None, "secret", 2, SALT).unwrap();
+        assert!(rehash_needed::<hasher::Argon2>(None, &bcrypt, 2));
+        assert!(rehash_needed::<hasher::Bcrypt>(None, &argon2, COST));
+        assert!(!rehash_needed::<hasher::Argon2>(None, &argon2, 2));
+        assert!(rehash_needed::<hasher::Argon2>(None, &argon2, 3));
+    }
+
+    #[test]
+    fn hash_algorithm_parses_known_names() {
+        assert_eq!("argon2".parse::<HashAlgorithm>(), Ok(HashAlgorithm::Argon2));
+        assert_eq!("bcrypt".parse::<HashAlgorithm>(), Ok(HashAlgorithm::Bcrypt));
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn debug_output_redacts_password() {
+        let password = StringPassword::<TestProperties>::new("hunter2 secret".to_string());
+        let debug = format!("{password:?}");
+        assert!(debug.contains("***"), "{debug}");
+        assert!(!debug.contains("hunter2"), "{debug}");
+    }
+}